@@ -0,0 +1,415 @@
+//! Biome
+//! Classifies regions into biomes once elevation, temperature and rainfall exist. Land is looked up in a Whittaker
+//! style table indexed by annual mean temperature and annual precipitation, and special surfaces override the table:
+//! ocean, lakes, beaches along the coast, marshes on wet flat lowland, glaciers where it is too cold or the cryosphere
+//! has ice, sea ice and alpine zones high up. Tables and override biomes can be replaced, so games can use their own
+//! biome types.
+//!
+use crate::{
+    cryosphere::{Cryosphere, Ice},
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    slope,
+    water::{self, Surface},
+    AnnualRainfall, HasBiome, HasElevation, HasTemperature,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Biome {
+    Ocean,
+    Lake,
+    Beach,
+    Marsh,
+    Glacier,
+    SeaIce,
+    Alpine,
+    Tundra,
+    BorealForest,
+    TemperateGrassland,
+    Shrubland,
+    TemperateDeciduousForest,
+    TemperateRainforest,
+    Desert,
+    Savanna,
+    TropicalSeasonalForest,
+    TropicalRainforest,
+}
+
+/// Lookup from annual mean temperature and annual precipitation to a biome.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Table<T: RealField, B> {
+    temperatures: Vec<T>,
+    precipitations: Vec<T>,
+    biomes: Vec<Vec<B>>,
+}
+impl<T: RealField, B: Copy> Table<T, B> {
+    /// `temperatures` and `precipitations` are ascending band edges, so `n` edges make `n + 1` bands. `biomes` holds
+    /// one row per temperature band from the coldest, each with one biome per precipitation band from the driest.
+    pub fn new(temperatures: Vec<T>, precipitations: Vec<T>, biomes: Vec<Vec<B>>) -> Result<Self, failure::Error> {
+        let ascending = |edges: &[T]| edges.windows(2).all(|pair| pair[0] < pair[1]);
+        if !ascending(&temperatures) || !ascending(&precipitations) {
+            return Err(failure::format_err!("Biome table band edges must be ascending"));
+        }
+        if biomes.len() != temperatures.len() + 1 || biomes.iter().any(|row| row.len() != precipitations.len() + 1) {
+            return Err(failure::format_err!(
+                "Biome table needs {} rows of {} biomes",
+                temperatures.len() + 1,
+                precipitations.len() + 1
+            ));
+        }
+
+        Ok(Self {
+            temperatures,
+            precipitations,
+            biomes,
+        })
+    }
+
+    pub fn lookup(&self, temperature: T, precipitation: T) -> B {
+        let band = |edges: &[T], value: T| edges.iter().take_while(|edge| **edge <= value).count();
+        self.biomes[band(&self.temperatures, temperature)][band(&self.precipitations, precipitation)]
+    }
+}
+impl<T: RealField + From<f32>> Table<T, Biome> {
+    /// Whittaker biomes by annual mean temperature in degrees Celsius and annual precipitation in millimeters.
+    pub fn whittaker() -> Self {
+        use Biome::{
+            BorealForest, Desert, Savanna, Shrubland, TemperateDeciduousForest, TemperateGrassland, TemperateRainforest, TropicalRainforest,
+            TropicalSeasonalForest, Tundra,
+        };
+
+        Self {
+            temperatures: vec![(-5.0).into(), 5.0.into(), 20.0.into()],
+            precipitations: vec![250.0.into(), 500.0.into(), 1000.0.into(), 2000.0.into()],
+            biomes: vec![
+                vec![Tundra, Tundra, Tundra, Tundra, Tundra],
+                vec![Tundra, BorealForest, BorealForest, BorealForest, BorealForest],
+                vec![Desert, TemperateGrassland, Shrubland, TemperateDeciduousForest, TemperateRainforest],
+                vec![Desert, Savanna, Savanna, TropicalSeasonalForest, TropicalRainforest],
+            ],
+        }
+    }
+}
+
+/// Biomes that replace the table lookup for special surfaces, `None` leaves the region to the table.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Overrides<B> {
+    pub ocean: Option<B>,
+    pub lake: Option<B>,
+    pub glacier: Option<B>,
+    pub sea_ice: Option<B>,
+    pub alpine: Option<B>,
+    pub beach: Option<B>,
+    pub marsh: Option<B>,
+}
+impl Overrides<Biome> {
+    pub fn default() -> Self {
+        Self {
+            ocean: Some(Biome::Ocean),
+            lake: Some(Biome::Lake),
+            glacier: Some(Biome::Glacier),
+            sea_ice: Some(Biome::SeaIce),
+            alpine: Some(Biome::Alpine),
+            beach: Some(Biome::Beach),
+            marsh: Some(Biome::Marsh),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField, B> {
+    table: Table<T, B>,
+    overrides: Overrides<B>,
+    sea_level: T,
+    glacier_temperature: T,
+    alpine_height: T,
+    beach_height: T,
+    marsh_height: T,
+    marsh_rainfall: T,
+    marsh_slope: T,
+}
+impl<T: RealField + From<f32>, B> Settings<T, B> {
+    /// Settings for a custom biome table, with the default thresholds.
+    pub fn new(table: Table<T, B>, overrides: Overrides<B>) -> Self {
+        Self {
+            table,
+            overrides,
+            sea_level: 0.3.into(),
+            glacier_temperature: (-10.0).into(),
+            alpine_height: 0.35.into(),
+            beach_height: 0.01.into(),
+            marsh_height: 0.05.into(),
+            marsh_rainfall: 1000.0.into(),
+            marsh_slope: 0.05.into(),
+        }
+    }
+
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Annual mean temperature below which land is covered by glaciers.
+    pub fn with_glacier_temperature(mut self, glacier_temperature: T) -> Self {
+        self.glacier_temperature = glacier_temperature;
+        self
+    }
+
+    /// Elevation above sea level where the alpine zone starts.
+    pub fn with_alpine_height(mut self, alpine_height: T) -> Self {
+        self.alpine_height = alpine_height;
+        self
+    }
+
+    /// Highest elevation above sea level of coastal regions that become beach.
+    pub fn with_beach_height(mut self, beach_height: T) -> Self {
+        self.beach_height = beach_height;
+        self
+    }
+
+    /// Highest elevation above sea level, lowest annual rainfall and steepest slope angle in radians of marshes.
+    pub fn with_marsh(mut self, marsh_height: T, marsh_rainfall: T, marsh_slope: T) -> Self {
+        self.marsh_height = marsh_height;
+        self.marsh_rainfall = marsh_rainfall;
+        self.marsh_slope = marsh_slope;
+        self
+    }
+}
+impl<T: RealField + From<f32>> Settings<T, Biome> {
+    pub fn default() -> Self {
+        Self::new(Table::whittaker(), Overrides::default())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Biomes<B> {
+    biomes: Vec<B>,
+}
+impl<B: Copy> Biomes<B> {
+    pub fn biome(&self, region: RegionNodeIdx) -> B {
+        self.biomes[region.index()]
+    }
+}
+
+pub fn visit<T, V, E, B>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T, B>) -> Result<Biomes<B>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasTemperature<T> + AnnualRainfall<T> + HasBiome<B>,
+    E: EdgeType,
+    B: Copy,
+{
+    classify(region_graph, settings, None)
+}
+
+/// Like `visit`, with glaciers wherever the cryosphere has ice on land instead of below the glacier temperature, and
+/// sea ice on frozen ocean.
+pub fn visit_with_cryosphere<T, V, E, B>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T, B>,
+    cryosphere: &Cryosphere<T>,
+) -> Result<Biomes<B>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasTemperature<T> + AnnualRainfall<T> + HasBiome<B>,
+    E: EdgeType,
+    B: Copy,
+{
+    classify(region_graph, settings, Some(cryosphere))
+}
+
+fn classify<T, V, E, B>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T, B>,
+    cryosphere: Option<&Cryosphere<T>>,
+) -> Result<Biomes<B>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasTemperature<T> + AnnualRainfall<T> + HasBiome<B>,
+    E: EdgeType,
+    B: Copy,
+{
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    let slopes = slope::visit(region_graph, &slope::Settings::default())?;
+    let overrides = &settings.overrides;
+
+    let biomes = region_graph
+        .node_indices()
+        .map(|region| {
+            let value = &region_graph[region].value;
+            let height = value.elevation() - settings.sea_level;
+            let temperature = value.temperature();
+            let rainfall = value.annual_rainfall();
+            let bordering = |surface: Surface| region_graph.neighbors(region).any(|neighbor| water.surface(neighbor) == surface);
+
+            let ice = cryosphere.and_then(|cryosphere| cryosphere.ice(region));
+            let special = match water.surface(region) {
+                Surface::Ocean if ice == Some(Ice::SeaIce) => overrides.sea_ice.or(overrides.ocean),
+                Surface::Ocean => overrides.ocean,
+                Surface::Lake if ice.is_some() => overrides.glacier.or(overrides.lake),
+                Surface::Lake => overrides.lake,
+                Surface::Land => {
+                    let wet = rainfall >= settings.marsh_rainfall && height <= settings.marsh_height;
+                    let glacier = cryosphere.map_or(temperature < settings.glacier_temperature, |_| ice.is_some());
+                    if glacier {
+                        overrides.glacier
+                    } else if height >= settings.alpine_height {
+                        overrides.alpine
+                    } else if height <= settings.beach_height && bordering(Surface::Ocean) {
+                        overrides.beach
+                    } else if wet && (slopes.slope(region) <= settings.marsh_slope || bordering(Surface::Lake)) {
+                        overrides.marsh
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            special.unwrap_or_else(|| settings.table.lookup(temperature, rainfall))
+        })
+        .collect::<Vec<_>>();
+
+    for (region, biome) in region_graph.node_weights_mut().zip(&biomes) {
+        region.value.set_biome(*biome);
+    }
+
+    Ok(Biomes { biomes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, tests::ClimateInner};
+    use crate::simple_wind::{self, Circulation};
+    use crate::{cryosphere, moisture, temperature};
+
+    struct Biomes {
+        biome: Biome,
+        zone: u8,
+    }
+    impl Default for Biomes {
+        fn default() -> Self {
+            Self {
+                biome: Biome::Ocean,
+                zone: 0,
+            }
+        }
+    }
+    type TestInner = ClimateInner<Biomes>;
+
+    impl HasBiome<Biome> for TestInner {
+        fn biome(&self) -> Biome {
+            self.data.biome
+        }
+        fn set_biome(&mut self, biome: Biome) {
+            self.data.biome = biome;
+        }
+    }
+    impl HasBiome<u8> for TestInner {
+        fn biome(&self) -> u8 {
+            self.data.zone
+        }
+        fn set_biome(&mut self, zone: u8) {
+            self.data.zone = zone;
+        }
+    }
+
+    fn color(biome: Biome) -> image::Rgb<u8> {
+        image::Rgb(match biome {
+            Biome::Ocean => [30, 60, 140],
+            Biome::Lake => [60, 110, 190],
+            Biome::Beach => [230, 215, 160],
+            Biome::Marsh => [80, 120, 100],
+            Biome::Glacier => [245, 250, 255],
+            Biome::SeaIce => [200, 215, 230],
+            Biome::Alpine => [160, 160, 150],
+            Biome::Tundra => [190, 200, 180],
+            Biome::BorealForest => [60, 100, 70],
+            Biome::TemperateGrassland => [170, 190, 100],
+            Biome::Shrubland => [160, 160, 90],
+            Biome::TemperateDeciduousForest => [80, 150, 60],
+            Biome::TemperateRainforest => [40, 120, 70],
+            Biome::Desert => [225, 200, 130],
+            Biome::Savanna => [190, 180, 80],
+            Biome::TropicalSeasonalForest => [100, 160, 40],
+            Biome::TropicalRainforest => [20, 110, 30],
+        })
+    }
+
+    #[test]
+    fn biome_table() {
+        let table = Table::<f32, Biome>::whittaker();
+        assert_eq!(table.lookup(-20.0, 3000.0), Biome::Tundra);
+        assert_eq!(table.lookup(0.0, 600.0), Biome::BorealForest);
+        assert_eq!(table.lookup(12.0, 100.0), Biome::Desert);
+        assert_eq!(table.lookup(12.0, 1500.0), Biome::TemperateDeciduousForest);
+        assert_eq!(table.lookup(26.0, 800.0), Biome::Savanna);
+        assert_eq!(table.lookup(26.0, 2500.0), Biome::TropicalRainforest);
+
+        assert!(Table::<f32, u8>::new(vec![0.0], vec![500.0], vec![vec![0, 1]]).is_err());
+        assert!(Table::<f32, u8>::new(vec![10.0, 0.0], vec![], vec![vec![0], vec![1], vec![2]]).is_err());
+    }
+
+    #[test]
+    pub fn biome_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        simple_wind::visit(&mut region_graph, &wind, &mut rng).unwrap();
+        let temperatures = temperature::visit(&mut region_graph, &temperature::Settings::<f32>::default()).unwrap();
+        moisture::visit(&mut region_graph, &moisture::Settings::<f32>::default()).unwrap();
+
+        let settings = Settings::<f32, Biome>::default();
+        let biomes = visit(&mut region_graph, &settings).unwrap();
+        for region in region_graph.node_indices() {
+            let value = &region_graph[region].value;
+            let biome = biomes.biome(region);
+            assert_eq!(HasBiome::<Biome>::biome(value), biome);
+            assert_eq!(value.elevation < 0.3, biome == Biome::Ocean || biome == Biome::Lake);
+            if value.temperature < -10.0 && value.elevation >= 0.3 {
+                assert_eq!(biome, Biome::Glacier);
+            }
+        }
+
+        // Ice from the cryosphere replaces the glacier temperature, and frozen ocean turns into sea ice
+        let cryosphere = cryosphere::visit(&region_graph, &temperatures, &cryosphere::Settings::default()).unwrap();
+        let frozen = visit_with_cryosphere(&mut region_graph, &settings, &cryosphere).unwrap();
+        for region in region_graph.node_indices() {
+            let biome = frozen.biome(region);
+            match cryosphere.ice(region) {
+                Some(Ice::SeaIce) => assert_eq!(biome, Biome::SeaIce),
+                Some(_) => assert_eq!(biome, Biome::Glacier),
+                None => assert!(biome != Biome::Glacier && biome != Biome::SeaIce),
+            }
+        }
+        assert!(region_graph.node_indices().any(|region| frozen.biome(region) == Biome::SeaIce));
+
+        // A custom table of climate zones with only the ocean overridden
+        let table = Table::new(vec![0.0, 20.0], vec![500.0], vec![vec![1, 2], vec![3, 4], vec![5, 6]]).unwrap();
+        let overrides = Overrides {
+            ocean: Some(0_u8),
+            lake: None,
+            glacier: None,
+            sea_ice: None,
+            alpine: None,
+            beach: None,
+            marsh: None,
+        };
+        let zones = visit(&mut region_graph, &Settings::new(table.clone(), overrides)).unwrap();
+        for region in region_graph.node_indices() {
+            let value = &region_graph[region].value;
+            let expected = if biomes.biome(region) == Biome::Ocean {
+                0
+            } else {
+                table.lookup(value.temperature, value.rainfall)
+            };
+            assert_eq!(zones.biome(region), expected);
+            assert_eq!(value.data.zone, expected);
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| color(region.value.data.biome));
+
+        imgbuf.save("output/biome.png").unwrap();
+    }
+}
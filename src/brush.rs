@@ -0,0 +1,312 @@
+//! Brush
+//! Interactive terrain editing. A brush stroke raises, lowers, smooths, flattens or roughens the elevation of every
+//! region within a radius of a position, weighted by a falloff from the center. Every stroke returns a change record
+//! holding the previous and new elevation of the regions it touched, so an editor can undo and redo strokes without
+//! keeping copies of the whole graph.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    noise::{self, Noise},
+    spatial::SpatialIndex,
+    HasElevation,
+};
+use nalgebra::{Point2, RealField};
+use petgraph::{EdgeType, Graph};
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Tool<T: RealField> {
+    Raise,
+    Lower,
+    /// Pulls regions towards the mean elevation of their neighbors.
+    Smooth,
+    /// Pulls regions towards the given elevation, or the elevation of the region under the brush center if `None`.
+    Flatten(Option<T>),
+    /// Adds noise within `[-strength, strength]`, sampled with the brush noise settings.
+    Noise,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Falloff {
+    /// Full strength over the whole radius.
+    Constant,
+    Linear,
+    /// Smoothstep from full strength at the center to zero at the radius.
+    Smooth,
+}
+impl Falloff {
+    /// Weight at `t`, the distance from the center as a fraction of the radius.
+    pub fn weight<T: RealField + From<f32>>(self, t: T) -> T {
+        let t = t.max(T::zero()).min(T::one());
+        match self {
+            Falloff::Constant => T::one(),
+            Falloff::Linear => T::one() - t,
+            Falloff::Smooth => T::one() - t * t * (T::from(3.0) - T::from(2.0) * t),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Change<T: RealField> {
+    pub region: RegionNodeIdx,
+    pub before: T,
+    pub after: T,
+}
+
+/// Elevations a stroke replaced, in the order they were written.
+#[derive(Clone, Debug)]
+pub struct ChangeRecord<T: RealField> {
+    changes: Vec<Change<T>>,
+}
+impl<T: RealField> ChangeRecord<T> {
+    pub fn changes(&self) -> &[Change<T>] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Restores the elevations from before the stroke.
+    pub fn undo<V, E>(&self, region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>)
+    where
+        V: HasElevation<T>,
+        E: EdgeType,
+    {
+        for change in self.changes.iter().rev() {
+            region_graph[change.region].value.set_elevation(change.before);
+        }
+    }
+
+    /// Writes the elevations of the stroke again after an `undo`.
+    pub fn redo<V, E>(&self, region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>)
+    where
+        V: HasElevation<T>,
+        E: EdgeType,
+    {
+        for change in &self.changes {
+            region_graph[change.region].value.set_elevation(change.after);
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    radius: T,
+    strength: T,
+    falloff: Falloff,
+    noise: noise::Settings<T>,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Radius in graph units.
+    pub fn with_radius(mut self, radius: T) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Elevation change at the center for `Raise`, `Lower` and `Noise`, and the blend factor towards the target for
+    /// `Smooth` and `Flatten`.
+    pub fn with_strength(mut self, strength: T) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_falloff(mut self, falloff: Falloff) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    /// Settings of the `Noise` tool, the sample is expected within `[0, 1]`.
+    pub fn with_noise(mut self, noise: noise::Settings<T>) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            radius: 50.0.into(),
+            strength: 0.05.into(),
+            falloff: Falloff::Smooth,
+            noise: noise::Settings::default().with_frequency((1.0 / 32.0).into()),
+        }
+    }
+}
+
+/// Applies one stroke of `tool` centered at `position` and returns what it changed.
+pub fn apply<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    index: &SpatialIndex,
+    tool: Tool<T>,
+    position: Point2<T>,
+    settings: &Settings<T>,
+    noise: &Noise,
+) -> Result<ChangeRecord<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.radius <= T::zero() {
+        return Err(failure::format_err!("Brush radius must be positive"));
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let to_f32 = |value: T| nalgebra::try_convert::<T, f64>(value).unwrap_or(0.0) as f32;
+    let center = Point2::new(to_f32(position.x), to_f32(position.y));
+
+    let target = match tool {
+        Tool::Flatten(Some(level)) => level,
+        Tool::Flatten(None) => match index.nearest(center) {
+            Some(region) => region_graph[region].value.elevation(),
+            None => return Ok(ChangeRecord { changes: Vec::new() }),
+        },
+        _ => T::zero(),
+    };
+
+    // Every new elevation is computed from the elevations before the stroke, so smoothing does not depend on the
+    // order regions are visited in
+    let mut regions = index.within(center, to_f32(settings.radius));
+    regions.sort();
+    let changes = regions
+        .into_iter()
+        .map(|region| {
+            let node = &region_graph[region];
+            let before = node.value.elevation();
+            let distance = T::from(nalgebra::distance(&node.pos, &center));
+            let weight = settings.strength * settings.falloff.weight(distance / settings.radius);
+            let blend = weight.max(T::zero()).min(T::one());
+
+            let after = match tool {
+                Tool::Raise => before + weight,
+                Tool::Lower => before - weight,
+                Tool::Smooth => {
+                    let (sum, count) = region_graph.neighbors(region).fold((T::zero(), 0), |(sum, count), neighbor| {
+                        (sum + region_graph[neighbor].value.elevation(), count + 1)
+                    });
+                    if count == 0 {
+                        before
+                    } else {
+                        #[allow(clippy::cast_precision_loss)]
+                        let mean = sum / T::from(count as f32);
+                        before + (mean - before) * blend
+                    }
+                }
+                Tool::Flatten(_) => before + (target - before) * blend,
+                Tool::Noise => {
+                    let sample = noise::sample(noise, &settings.noise, Point2::new(T::from(node.pos.x), T::from(node.pos.y)));
+                    before + weight * (sample * T::from(2.0) - T::one())
+                }
+            };
+
+            Change { region, before, after }
+        })
+        .filter(|change| change.after != change.before)
+        .collect::<Vec<_>>();
+
+    let record = ChangeRecord { changes };
+    record.redo(region_graph);
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn brush_strokes() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let index = SpatialIndex::new(&region_graph);
+        let noise = Noise::new(&mut rng);
+        let elevations = |graph: &crate::dual_graph::RegionGraph<TestInner>| {
+            graph.raw_nodes().iter().map(|node| node.weight.value.elevation()).collect::<Vec<_>>()
+        };
+        let original = elevations(&region_graph);
+
+        // Raising only touches regions inside the radius, most at the center
+        let settings = Settings::<f32>::default().with_radius(100.0).with_strength(0.2);
+        let raise = apply(&mut region_graph, &index, Tool::Raise, Point2::new(300.0, 300.0), &settings, &noise).unwrap();
+        assert!(!raise.is_empty());
+        for change in raise.changes() {
+            assert!(nalgebra::distance(&region_graph[change.region].pos, &Point2::new(300.0, 300.0)) <= 100.0);
+            assert!(change.after > change.before && change.after - change.before <= 0.2 + std::f32::EPSILON);
+        }
+        let raised = elevations(&region_graph);
+
+        // Flattening at full strength levels the whole disc
+        let settings = Settings::<f32>::default()
+            .with_radius(80.0)
+            .with_strength(1.0)
+            .with_falloff(Falloff::Constant);
+        let flatten = apply(
+            &mut region_graph,
+            &index,
+            Tool::Flatten(Some(0.5)),
+            Point2::new(700.0, 500.0),
+            &settings,
+            &noise,
+        )
+        .unwrap();
+        for region in index.within(Point2::new(700.0, 500.0), 80.0) {
+            assert!((region_graph[region].value.elevation() - 0.5).abs() < std::f32::EPSILON);
+        }
+
+        // Smoothing reduces the roughness of the disc
+        let roughness = |graph: &crate::dual_graph::RegionGraph<TestInner>| {
+            index
+                .within(Point2::new(500.0, 700.0), 120.0)
+                .into_iter()
+                .map(|region| {
+                    let elevation = graph[region].value.elevation();
+                    graph
+                        .neighbors(region)
+                        .map(|neighbor| (graph[neighbor].value.elevation() - elevation).abs())
+                        .sum::<f32>()
+                })
+                .sum::<f32>()
+        };
+        let noise_settings = Settings::<f32>::default().with_radius(150.0).with_strength(0.1);
+        let roughen = apply(&mut region_graph, &index, Tool::Noise, Point2::new(500.0, 700.0), &noise_settings, &noise).unwrap();
+        let rough = roughness(&region_graph);
+        let settings = Settings::<f32>::default().with_radius(150.0).with_strength(1.0);
+        let smooth = apply(&mut region_graph, &index, Tool::Smooth, Point2::new(500.0, 700.0), &settings, &noise).unwrap();
+        assert!(roughness(&region_graph) < rough);
+        let lower = apply(&mut region_graph, &index, Tool::Lower, Point2::new(200.0, 800.0), &settings, &noise).unwrap();
+        let edited = elevations(&region_graph);
+
+        // Undoing in reverse order restores the terrain exactly, redoing replays it
+        for record in &[&lower, &smooth, &roughen, &flatten] {
+            record.undo(&mut region_graph);
+        }
+        assert_eq!(elevations(&region_graph), raised);
+        raise.undo(&mut region_graph);
+        assert_eq!(elevations(&region_graph), original);
+        for record in &[&raise, &flatten, &roughen, &smooth, &lower] {
+            record.redo(&mut region_graph);
+        }
+        assert_eq!(elevations(&region_graph), edited);
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/brush.png").unwrap();
+    }
+}
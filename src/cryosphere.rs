@@ -0,0 +1,374 @@
+//! Cryosphere
+//! Places permanent ice. Ice caps cover regions whose annual mean or coldest month temperature is below a threshold.
+//! The snowline is estimated for every latitude band from the warmest month temperature reduced to sea level, and land
+//! above it gains ice while land below it melts ice away. The ice is passed downhill along the flow routing, so
+//! glaciers reach down valleys until melting has used up everything that flows in from above. Cold ocean can freeze
+//! over with sea ice. Biomes can take the ice into account with `biome::visit_with_cryosphere`.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    flow,
+    latitude::Latitude,
+    temperature::Temperatures,
+    water::{self, Surface},
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Ice {
+    Cap,
+    Glacier,
+    SeaIce,
+}
+
+/// Latitude band in degrees and the elevation of its snowline.
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Band<T: RealField> {
+    pub south: T,
+    pub north: T,
+    pub snowline: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cryosphere<T: RealField> {
+    ice: Vec<Option<Ice>>,
+    fluxes: Vec<T>,
+    snowlines: Vec<T>,
+    bands: Vec<Band<T>>,
+}
+impl<T: RealField> Cryosphere<T> {
+    pub fn ice(&self, region: RegionNodeIdx) -> Option<Ice> {
+        self.ice[region.index()]
+    }
+
+    /// Ice flowing out of a land region, in kilometers of climb above the snowline per region.
+    pub fn ice_flux(&self, region: RegionNodeIdx) -> T {
+        self.fluxes[region.index()]
+    }
+
+    /// Snowline elevation of the latitude band the region lies in.
+    pub fn snowline(&self, region: RegionNodeIdx) -> T {
+        self.snowlines[region.index()]
+    }
+
+    /// Latitude bands containing regions, from south to north.
+    pub fn bands(&self) -> &[Band<T>] {
+        &self.bands
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    latitude: Latitude<T>,
+    sea_level: T,
+    annual: T,
+    coldest_month: T,
+    lapse_rate: T,
+    height_scale: T,
+    band_width: T,
+    snowline_temperature: T,
+    accumulation: T,
+    ablation: T,
+    sea_ice: Option<T>,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_latitude(mut self, latitude: Latitude<T>) -> Self {
+        self.latitude = latitude;
+        self
+    }
+
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Annual mean and coldest month temperatures below which regions are covered by an ice cap.
+    pub fn with_ice_cap(mut self, annual: T, coldest_month: T) -> Self {
+        self.annual = annual;
+        self.coldest_month = coldest_month;
+        self
+    }
+
+    /// Cooling in degrees per kilometer of height, and meters of height per unit of elevation above sea level. Should
+    /// match the temperature stage.
+    pub fn with_lapse_rate(mut self, lapse_rate: T, height_scale: T) -> Self {
+        self.lapse_rate = lapse_rate;
+        self.height_scale = height_scale;
+        self
+    }
+
+    /// Degrees of latitude every snowline is estimated over.
+    pub fn with_band_width(mut self, band_width: T) -> Self {
+        self.band_width = band_width;
+        self
+    }
+
+    /// Warmest month temperature at the snowline.
+    pub fn with_snowline_temperature(mut self, snowline_temperature: T) -> Self {
+        self.snowline_temperature = snowline_temperature;
+        self
+    }
+
+    /// Ice gained per kilometer above the snowline and lost per kilometer below it. Ice caps gain as much as land a
+    /// kilometer above the snowline.
+    pub fn with_mass_balance(mut self, accumulation: T, ablation: T) -> Self {
+        self.accumulation = accumulation;
+        self.ablation = ablation;
+        self
+    }
+
+    /// Coldest month temperature below which the ocean freezes over, `None` leaves the ocean open.
+    pub fn with_sea_ice(mut self, sea_ice: Option<T>) -> Self {
+        self.sea_ice = sea_ice;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            latitude: Latitude::default(),
+            sea_level: 0.3.into(),
+            annual: (-10.0).into(),
+            coldest_month: (-35.0).into(),
+            lapse_rate: 6.5.into(),
+            height_scale: 8000.0.into(),
+            band_width: 10.0.into(),
+            snowline_temperature: 0.0.into(),
+            accumulation: 1.0.into(),
+            ablation: 2.0.into(),
+            sea_ice: Some((-10.0).into()),
+        }
+    }
+}
+
+/// Snowline of every region and the latitude bands they were estimated over. The warmest month reduced to sea level
+/// gives the height where it cools down to the snowline temperature.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+fn snowlines<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    temperatures: &Temperatures<T>,
+    settings: &Settings<T>,
+) -> (Vec<T>, Vec<Band<T>>)
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    let latitudes = settings.latitude.visit(region_graph);
+    let kilometers = |region: RegionNodeIdx| {
+        (region_graph[region].value.elevation() - settings.sea_level).max(T::zero()) * settings.height_scale / T::from(1000.0)
+    };
+
+    let to_index = |value: T| nalgebra::try_convert::<T, f64>(value).unwrap_or(0.0).max(0.0) as usize;
+    let band_count = to_index((T::from(180.0) / settings.band_width).ceil()).max(1);
+    let band_of = |region: RegionNodeIdx| {
+        let offset = (latitudes.latitude(region) + T::from(90.0)) / settings.band_width;
+        to_index(offset.floor()).min(band_count - 1)
+    };
+    let mut sums = vec![(T::zero(), 0_usize); band_count];
+    for region in region_graph.node_indices() {
+        let sea_level_warmest = temperatures.warmest(region) + settings.lapse_rate * kilometers(region);
+        let sum = &mut sums[band_of(region)];
+        *sum = (sum.0 + sea_level_warmest, sum.1 + 1);
+    }
+    let band_snowlines = sums
+        .iter()
+        .map(|(sum, count)| {
+            let warmest = *sum / T::from((*count).max(1) as f32);
+            let height = ((warmest - settings.snowline_temperature) / settings.lapse_rate).max(T::zero());
+            settings.sea_level + height * T::from(1000.0) / settings.height_scale
+        })
+        .collect::<Vec<_>>();
+    let bands = sums
+        .iter()
+        .zip(&band_snowlines)
+        .enumerate()
+        .filter(|(_, ((_, count), _))| *count > 0)
+        .map(|(band, (_, snowline))| {
+            let south = settings.band_width * T::from(band as f32) - T::from(90.0);
+            Band {
+                south,
+                north: (south + settings.band_width).min(T::from(90.0)),
+                snowline: *snowline,
+            }
+        })
+        .collect::<Vec<_>>();
+    let snowlines = region_graph
+        .node_indices()
+        .map(|region| band_snowlines[band_of(region)])
+        .collect::<Vec<_>>();
+
+    (snowlines, bands)
+}
+
+/// Finds ice from the temperatures of `temperature::visit` over the same graph.
+pub fn visit<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    temperatures: &Temperatures<T>,
+    settings: &Settings<T>,
+) -> Result<Cryosphere<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.band_width <= T::zero() {
+        return Err(failure::format_err!("Snowline band width must be positive"));
+    }
+    if settings.lapse_rate <= T::zero() {
+        return Err(failure::format_err!("Lapse rate must be positive"));
+    }
+    if settings.accumulation < T::zero() || settings.ablation < T::zero() {
+        return Err(failure::format_err!("Ice mass balance must not be negative"));
+    }
+
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    let (snowlines, bands) = snowlines(region_graph, temperatures, settings);
+
+    let caps = region_graph
+        .node_indices()
+        .map(|region| {
+            water.surface(region) != Surface::Ocean
+                && (temperatures.mean(region) < settings.annual || temperatures.coldest(region) < settings.coldest_month)
+        })
+        .collect::<Vec<_>>();
+
+    // Ice flows along the flow routing over land, passing on what is left after the region gained or lost its share.
+    // Glaciers reaching the ocean or a lake calve into it
+    let to_kilometers = settings.height_scale / T::from(1000.0);
+    let mut fluxes = region_graph
+        .node_indices()
+        .map(|region| {
+            if water.surface(region) != Surface::Land && !caps[region.index()] {
+                return T::zero();
+            }
+            let above = (region_graph[region].value.elevation() - snowlines[region.index()]) * to_kilometers;
+            let balance = if above > T::zero() {
+                above * settings.accumulation
+            } else {
+                above * settings.ablation
+            };
+            if caps[region.index()] {
+                balance.max(settings.accumulation)
+            } else {
+                balance
+            }
+        })
+        .collect::<Vec<_>>();
+    let elevations = region_graph
+        .node_indices()
+        .map(|region| region_graph[region].value.elevation())
+        .collect::<Vec<_>>();
+    let routing = flow::route(region_graph, &elevations, &flow::Settings::default());
+    for region in routing.upstream_order() {
+        let flux = fluxes[region.index()].max(T::zero());
+        fluxes[region.index()] = flux;
+        if let Some(next) = routing.receiver(region) {
+            if water.surface(next) == Surface::Land {
+                fluxes[next.index()] += flux;
+            }
+        }
+    }
+
+    let ice = region_graph
+        .node_indices()
+        .map(|region| {
+            if caps[region.index()] {
+                Some(Ice::Cap)
+            } else if water.is_ocean(region) {
+                settings
+                    .sea_ice
+                    .filter(|freezing| temperatures.coldest(region) < *freezing)
+                    .map(|_| Ice::SeaIce)
+            } else if fluxes[region.index()] > T::zero() {
+                Some(Ice::Glacier)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    Ok(Cryosphere {
+        ice,
+        fluxes,
+        snowlines,
+        bands,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, tests::ClimateInner};
+    use crate::simple_wind::{self, Circulation};
+    use crate::temperature;
+
+    type TestInner = ClimateInner<Option<Ice>>;
+
+    #[test]
+    pub fn cryosphere_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        simple_wind::visit(&mut region_graph, &wind, &mut rng).unwrap();
+        let temperatures = temperature::visit(&mut region_graph, &temperature::Settings::<f32>::default()).unwrap();
+
+        let settings = Settings::<f32>::default();
+        let cryosphere = visit(&region_graph, &temperatures, &settings).unwrap();
+
+        // Snowlines drop from the tropics towards the poles
+        let bands = cryosphere.bands();
+        assert!(bands.len() >= 16);
+        let equator = bands.iter().find(|band| band.south <= 0.0 && band.north > 0.0).unwrap();
+        assert!(bands.first().unwrap().snowline < equator.snowline);
+        assert!(bands.last().unwrap().snowline < equator.snowline);
+        assert!(equator.snowline > 0.3 + 4000.0 / 8000.0);
+
+        for region in region_graph.node_indices() {
+            let elevation = region_graph[region].value.elevation;
+            match cryosphere.ice(region) {
+                Some(Ice::Cap) => assert!(temperatures.mean(region) < -10.0 || temperatures.coldest(region) < -35.0),
+                Some(Ice::SeaIce) => assert!(elevation < 0.3 && temperatures.coldest(region) < -10.0),
+                Some(Ice::Glacier) => assert!(elevation >= 0.3 && cryosphere.ice_flux(region) > 0.0),
+                None => assert!(elevation < cryosphere.snowline(region) || elevation < 0.3),
+            }
+        }
+
+        // Lower thresholds cover all cold land in ice caps
+        let capped = visit(&region_graph, &temperatures, &settings.clone().with_ice_cap(0.0, -35.0)).unwrap();
+        for region in region_graph.node_indices() {
+            if region_graph[region].value.elevation >= 0.3 && temperatures.mean(region) < 0.0 {
+                assert_eq!(capped.ice(region), Some(Ice::Cap));
+            }
+        }
+        assert!(region_graph.node_indices().any(|region| capped.ice(region) == Some(Ice::Cap)));
+
+        // Glaciers flow below the snowline
+        assert!(region_graph
+            .node_indices()
+            .any(|region| { cryosphere.ice(region) == Some(Ice::Glacier) && region_graph[region].value.elevation < cryosphere.snowline(region) }));
+
+        // Without sea ice the ocean stays open
+        let open = visit(&region_graph, &temperatures, &settings.clone().with_sea_ice(None)).unwrap();
+        assert!(region_graph.node_indices().all(|region| open.ice(region) != Some(Ice::SeaIce)));
+        assert!(visit(&region_graph, &temperatures, &settings.with_band_width(0.0)).is_err());
+
+        for region in region_graph.node_indices() {
+            region_graph[region].value.data = cryosphere.ice(region);
+        }
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let shade = (200.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+            match region.value.data {
+                Some(Ice::Cap) => image::Rgb([250, 250, 255]),
+                Some(Ice::Glacier) => image::Rgb([170, 220, 250]),
+                Some(Ice::SeaIce) => image::Rgb([200, 215, 230]),
+                None if region.value.elevation() < 0.3 => image::Rgb([0, 0, 128]),
+                None => image::Rgb([shade / 2, shade, shade / 2]),
+            }
+        });
+
+        imgbuf.save("output/cryosphere.png").unwrap();
+    }
+}
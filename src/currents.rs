@@ -0,0 +1,458 @@
+//! Currents
+//! Wind driven ocean surface currents. The curl of the wind stress over the ocean drives a stream function that is held
+//! at zero along coasts and the edge of the map, so the currents close into gyres inside every basin and never flow
+//! onto land. Sea surface temperature set by latitude is carried along the currents, and the difference to the local
+//! temperature is the anomaly of the water: currents flowing polewards are warm, currents flowing towards the equator
+//! cold. Land along the ocean is classified by the anomaly of the water off its coast, and the temperature and
+//! moisture stages take the anomaly into account through their `visit_with_currents` variants.
+//!
+use crate::{
+    dual_graph::{is_boundary_region, RegionEdge, RegionNode, RegionNodeIdx},
+    latitude::Latitude,
+    slope::{self, fit_gradient},
+    water::{self, Surface},
+    HasElevation, HasWind,
+};
+use nalgebra::{RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CoastalCurrent {
+    Warm,
+    Cold,
+}
+
+#[derive(Clone, Debug)]
+pub struct Currents<T: RealField> {
+    velocities: Vec<Vector2<T>>,
+    anomalies: Vec<T>,
+    coasts: Vec<Option<CoastalCurrent>>,
+}
+impl<T: RealField> Currents<T> {
+    /// Surface current of an ocean region, zero on land and lakes.
+    pub fn current(&self, region: RegionNodeIdx) -> Vector2<T> {
+        self.velocities[region.index()]
+    }
+
+    /// Sea surface temperature brought by the currents relative to the temperature of the latitude, in degrees. Zero on
+    /// land and lakes.
+    pub fn anomaly(&self, region: RegionNodeIdx) -> T {
+        self.anomalies[region.index()]
+    }
+
+    /// Current off the coast of a land region, `None` inland and along water without a clear anomaly.
+    pub fn coast(&self, region: RegionNodeIdx) -> Option<CoastalCurrent> {
+        self.coasts[region.index()]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    latitude: Latitude<T>,
+    sea_level: T,
+    equator: T,
+    pole: T,
+    speed: T,
+    relaxation: T,
+    iterations: usize,
+    exchange: T,
+    advection_steps: usize,
+    coastal_anomaly: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_latitude(mut self, latitude: Latitude<T>) -> Self {
+        self.latitude = latitude;
+        self
+    }
+
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Sea surface temperature at the equator and at the poles without currents.
+    pub fn with_sea_surface_temperatures(mut self, equator: T, pole: T) -> Self {
+        self.equator = equator;
+        self.pole = pole;
+        self
+    }
+
+    /// Speed of the fastest current, the other currents are scaled to it.
+    pub fn with_speed(mut self, speed: T) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Over-relaxation factor within `(0, 2)` and the largest number of iterations solving for the stream function.
+    pub fn with_solver(mut self, relaxation: T, iterations: usize) -> Self {
+        self.relaxation = relaxation;
+        self.iterations = iterations;
+        self
+    }
+
+    /// How fast the water takes on the temperature of its latitude, relative to the fastest current. Water keeps half
+    /// its temperature when crossing a region at this fraction of the fastest speed. Also the number of steps the
+    /// temperature is carried.
+    pub fn with_heat_exchange(mut self, exchange: T, advection_steps: usize) -> Self {
+        self.exchange = exchange;
+        self.advection_steps = advection_steps;
+        self
+    }
+
+    /// Smallest anomaly in degrees off a coast that makes it a warm or cold current coast.
+    pub fn with_coastal_anomaly(mut self, coastal_anomaly: T) -> Self {
+        self.coastal_anomaly = coastal_anomaly;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            latitude: Latitude::default(),
+            sea_level: 0.3.into(),
+            equator: 28.0.into(),
+            pole: (-2.0).into(),
+            speed: 1.0.into(),
+            relaxation: 1.8.into(),
+            iterations: 2000,
+            exchange: 0.05.into(),
+            advection_steps: 200,
+            coastal_anomaly: 1.0.into(),
+        }
+    }
+}
+
+/// Stream function whose Laplacian, the vorticity of the currents, follows the curl of the wind stress. Solved by
+/// over-relaxation on the distance weighted graph Laplacian, with the `fixed` regions held at zero.
+fn stream_function<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, fixed: &[bool], curls: &[T], settings: &Settings<T>) -> Vec<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    let count = region_graph.node_count();
+    let weights = slope::laplacian_weights(region_graph);
+    let mut stream = vec![T::zero(); count];
+    for _ in 0..settings.iterations {
+        let (mut change, mut largest) = (T::zero(), T::zero());
+        for region in 0..count {
+            if fixed[region] {
+                continue;
+            }
+            let (sum, total) = weights[region].iter().fold((T::zero(), T::zero()), |(sum, total), (neighbor, weight)| {
+                (sum + stream[neighbor.index()] * *weight, total + *weight)
+            });
+            if total <= T::zero() {
+                continue;
+            }
+
+            let relaxed = (sum - curls[region]) / total;
+            let step = (relaxed - stream[region]) * settings.relaxation;
+            stream[region] += step;
+            change = change.max(step.abs());
+            largest = largest.max(stream[region].abs());
+        }
+        if change <= largest * T::from(1.0e-5) {
+            break;
+        }
+    }
+
+    stream
+}
+
+/// Sea surface temperature carried along the currents from the `local` temperature of each region. Water loses its
+/// temperature to the local one the slower it flows.
+fn carry<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    currents: &[Vector2<T>],
+    ocean: &[bool],
+    local: &[T],
+    settings: &Settings<T>,
+) -> Vec<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    let is_ocean = |region: RegionNodeIdx| ocean[region.index()];
+    let upstream = region_graph
+        .node_indices()
+        .map(|region| {
+            let position = region_graph[region].pos;
+            region_graph
+                .neighbors(region)
+                .filter(|neighbor| is_ocean(*neighbor) && is_ocean(region))
+                .filter_map(|neighbor| {
+                    let offset = position - region_graph[neighbor].pos;
+                    let offset = Vector2::new(T::from(offset.x), T::from(offset.y));
+                    let length = offset.norm();
+                    let weight = if length > T::zero() {
+                        currents[neighbor.index()].dot(&offset) / length
+                    } else {
+                        T::zero()
+                    };
+                    if weight > T::zero() {
+                        Some((neighbor, weight))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut temperatures = local.to_vec();
+    let mut next = local.to_vec();
+    for _ in 0..settings.advection_steps {
+        for region in 0..local.len() {
+            let (sum, total) = upstream[region].iter().fold((T::zero(), T::zero()), |(sum, total), (neighbor, weight)| {
+                (sum + temperatures[neighbor.index()] * *weight, total + *weight)
+            });
+            next[region] = if total > T::zero() && settings.speed > T::zero() {
+                let relative = currents[region].norm() / settings.speed;
+                let fraction = relative / (relative + settings.exchange);
+                local[region] * (T::one() - fraction) + sum / total * fraction
+            } else {
+                local[region]
+            };
+        }
+        std::mem::swap(&mut temperatures, &mut next);
+    }
+
+    temperatures
+}
+
+pub fn visit<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Currents<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T>,
+    E: EdgeType,
+{
+    if settings.relaxation <= T::zero() || settings.relaxation >= T::from(2.0) {
+        return Err(failure::format_err!("Current solver relaxation must be within (0, 2)"));
+    }
+    if settings.exchange <= T::zero() {
+        return Err(failure::format_err!("Current heat exchange must be positive"));
+    }
+    if settings.speed < T::zero() {
+        return Err(failure::format_err!("Current speed must not be negative"));
+    }
+
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    let count = region_graph.node_count();
+    let ocean = region_graph.node_indices().map(|region| water.is_ocean(region)).collect::<Vec<_>>();
+    let is_ocean = |region: RegionNodeIdx| ocean[region.index()];
+
+    // Water along coasts and the map edge has no flow across it, which keeps the stream function constant there
+    let fixed = region_graph
+        .node_indices()
+        .map(|region| !is_ocean(region) || is_boundary_region(region_graph, region) || !region_graph.neighbors(region).all(is_ocean))
+        .collect::<Vec<_>>();
+
+    // Quadratic drag of the wind on the water surface
+    let stress = region_graph
+        .node_indices()
+        .map(|region| {
+            let wind = region_graph[region].value.wind_vector();
+            wind * wind.norm()
+        })
+        .collect::<Vec<_>>();
+    let stress_x = stress.iter().map(|stress| stress.x).collect::<Vec<_>>();
+    let stress_y = stress.iter().map(|stress| stress.y).collect::<Vec<_>>();
+    let curls = region_graph
+        .node_indices()
+        .map(|region| {
+            if fixed[region.index()] {
+                T::zero()
+            } else {
+                fit_gradient(region_graph, region, &stress_y, is_ocean).x - fit_gradient(region_graph, region, &stress_x, is_ocean).y
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let stream = stream_function(region_graph, &fixed, &curls, settings);
+
+    let mut currents = region_graph
+        .node_indices()
+        .map(|region| {
+            if is_ocean(region) {
+                let slope = fit_gradient(region_graph, region, &stream, |_| true);
+                Vector2::new(-slope.y, slope.x)
+            } else {
+                Vector2::zeros()
+            }
+        })
+        .collect::<Vec<_>>();
+    let fastest = currents.iter().fold(T::zero(), |fastest, current| fastest.max(current.norm()));
+    if fastest > T::zero() {
+        for current in &mut currents {
+            *current *= settings.speed / fastest;
+        }
+    }
+
+    // Carry the sea surface temperature of each latitude along the currents
+    let latitudes = settings.latitude.visit(region_graph);
+    let local = region_graph
+        .node_indices()
+        .map(|region| {
+            let latitude = (latitudes.latitude(region) * T::pi() / T::from(180.0)).sin();
+            settings.equator + (settings.pole - settings.equator) * latitude * latitude
+        })
+        .collect::<Vec<_>>();
+    let temperatures = carry(region_graph, &currents, &ocean, &local, settings);
+    let anomalies = (0..count)
+        .map(|region| {
+            if ocean[region] {
+                temperatures[region] - local[region]
+            } else {
+                T::zero()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let coasts = region_graph
+        .node_indices()
+        .map(|region| {
+            if water.surface(region) != Surface::Land {
+                return None;
+            }
+            let (sum, total) = region_graph
+                .neighbors(region)
+                .filter(|neighbor| is_ocean(*neighbor))
+                .fold((T::zero(), T::zero()), |(sum, total), neighbor| {
+                    (sum + anomalies[neighbor.index()], total + T::one())
+                });
+            if total <= T::zero() {
+                None
+            } else if sum / total >= settings.coastal_anomaly {
+                Some(CoastalCurrent::Warm)
+            } else if sum / total <= -settings.coastal_anomaly {
+                Some(CoastalCurrent::Cold)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    Ok(Currents {
+        velocities: currents,
+        anomalies,
+        coasts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate, tests::ClimateInner};
+    use crate::simple_wind::{self, Circulation};
+    use crate::{moisture, temperature};
+    use nalgebra::Point2;
+
+    #[derive(Default)]
+    struct Coast {
+        anomaly: f32,
+        current: Option<CoastalCurrent>,
+    }
+    type TestInner = ClimateInner<Coast>;
+
+    #[test]
+    fn currents_basin() {
+        let (mut region_graph, _, _) = peak_automata::tests::test_graph::<TestInner>();
+
+        // A northern basin between two continents opening to the south, westerlies in the north and trade winds in the south
+        for region in region_graph.node_weights_mut() {
+            let basin = region.pos.x > 200.0 && region.pos.x < 824.0 && region.pos.y > 100.0;
+            region.value.elevation = if basin { 0.0 } else { 0.4 };
+            region.value.wind = Vector2::new(8.0 * (std::f32::consts::PI * region.pos.y / 1024.0).cos(), 0.0);
+        }
+        let settings = Settings::<f32>::default().with_latitude(Latitude::Planar { top: 55.0, bottom: 5.0 });
+        let currents = visit(&region_graph, &settings).unwrap();
+        let at = |x: f32, y: f32| node_for_coordinate(&region_graph, Point2::new(x, y)).unwrap();
+
+        // The gyre turns clockwise, north along the west of the basin and south along the east
+        assert!(currents.current(at(250.0, 512.0)).y < 0.0);
+        assert!(currents.current(at(774.0, 512.0)).y > 0.0);
+        assert!(currents.current(at(512.0, 250.0)).x > 0.0);
+
+        // Land regions along the basin closest to a point
+        let shore = |x: f32, y: f32| {
+            region_graph
+                .node_indices()
+                .filter(|region| {
+                    region_graph[*region].value.elevation >= 0.3
+                        && region_graph
+                            .neighbors(*region)
+                            .any(|neighbor| region_graph[neighbor].value.elevation < 0.3)
+                })
+                .min_by(|a, b| {
+                    let distance = |region: RegionNodeIdx| nalgebra::distance(&region_graph[region].pos, &Point2::new(x, y));
+                    distance(*a).partial_cmp(&distance(*b)).unwrap()
+                })
+                .unwrap()
+        };
+        let (ocean, warm, cold) = (at(250.0, 400.0), shore(200.0, 400.0), shore(824.0, 600.0));
+        assert!(currents.anomaly(ocean) > 0.0);
+        assert!(currents.anomaly(at(774.0, 600.0)) < 0.0);
+        assert_eq!(currents.coast(warm), Some(CoastalCurrent::Warm));
+        assert_eq!(currents.coast(cold), Some(CoastalCurrent::Cold));
+        let fastest = region_graph
+            .node_indices()
+            .fold(0.0_f32, |fastest, region| fastest.max(currents.current(region).norm()));
+        assert!((fastest - 1.0).abs() < 1.0e-4);
+        for region in region_graph.node_indices() {
+            if region_graph[region].value.elevation >= 0.3 {
+                assert_eq!(currents.current(region), Vector2::zeros());
+                assert!(currents.anomaly(region).abs() < std::f32::EPSILON);
+            } else {
+                assert!(currents.coast(region).is_none());
+            }
+        }
+
+        // Turned around, the winds blow onshore and carry the anomaly to the coasts
+        for region in region_graph.node_weights_mut() {
+            region.value.wind = -region.value.wind;
+        }
+        let temperature = temperature::Settings::<f32>::default().with_latitude(Latitude::Planar { top: 55.0, bottom: 5.0 });
+        let moisture = moisture::Settings::<f32>::default();
+        let plain = temperature::visit(&mut region_graph, &temperature).unwrap();
+        let dry = moisture::visit(&mut region_graph, &moisture).unwrap();
+        let adjusted = temperature::visit_with_currents(&mut region_graph, &temperature, &currents).unwrap();
+        let wet = moisture::visit_with_currents(&mut region_graph, &moisture, &currents).unwrap();
+        assert!(adjusted.mean(warm) > plain.mean(warm));
+        assert!(adjusted.mean(cold) < plain.mean(cold));
+        assert!(wet.rainfall(warm) > dry.rainfall(warm));
+        assert!(wet.rainfall(cold) < dry.rainfall(cold));
+    }
+
+    #[test]
+    pub fn currents_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        simple_wind::visit(&mut region_graph, &wind, &mut rng).unwrap();
+        let currents = visit(&region_graph, &Settings::default()).unwrap();
+        assert!(region_graph
+            .node_indices()
+            .any(|region| currents.coast(region) == Some(CoastalCurrent::Warm)));
+        assert!(region_graph
+            .node_indices()
+            .any(|region| currents.coast(region) == Some(CoastalCurrent::Cold)));
+
+        for region in region_graph.node_indices() {
+            region_graph[region].value.data.anomaly = currents.anomaly(region);
+            region_graph[region].value.data.current = currents.coast(region);
+        }
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            if region.value.elevation < 0.3 {
+                let warmth = (region.value.data.anomaly / 5.0).min(1.0).max(-1.0);
+                image::Rgb([(80.0 + 120.0 * warmth.max(0.0)) as u8, 60, (140.0 - 60.0 * warmth.min(0.0)) as u8])
+            } else {
+                match region.value.data.current {
+                    Some(CoastalCurrent::Warm) => image::Rgb([230, 90, 60]),
+                    Some(CoastalCurrent::Cold) => image::Rgb([60, 170, 230]),
+                    None => image::Rgb([120, 150, 90]),
+                }
+            }
+        });
+
+        imgbuf.save("output/currents.png").unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+//! Distance
+//! Multi-source Dijkstra over the region graph, measuring the Euclidean length of the edges between region positions.
+//! Every region gets its distance to the closest source and which source that is, so rules based on distance to a
+//! coast, river, mountain or capital can share one implementation. A cost function can reweight or block edges.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    scored::MinScored,
+    water::Water,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+use std::collections::BinaryHeap;
+
+#[derive(Clone, Debug)]
+pub struct DistanceField<T: RealField> {
+    distances: Vec<Option<T>>,
+    nearest: Vec<Option<RegionNodeIdx>>,
+}
+impl<T: RealField> DistanceField<T> {
+    /// Distance from the region to the closest source, `None` if no source can be reached.
+    pub fn distance(&self, region: RegionNodeIdx) -> Option<T> {
+        self.distances[region.index()]
+    }
+
+    /// Closest source to the region, `None` if no source can be reached.
+    pub fn nearest(&self, region: RegionNodeIdx) -> Option<RegionNodeIdx> {
+        self.nearest[region.index()]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    max_distance: Option<T>,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Stop searching past this distance, regions further away are left unreached.
+    pub fn with_max_distance(mut self, max_distance: Option<T>) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self { max_distance: None }
+    }
+}
+
+/// Distance from the given source regions along the region graph.
+pub fn visit<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    sources: &[RegionNodeIdx],
+    settings: &Settings<T>,
+) -> Result<DistanceField<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    visit_with_cost(region_graph, sources, settings, |_, _, length| Some(length))
+}
+
+/// Distance from the given source regions, where `cost(from, to, length)` gives the cost of stepping between two
+/// neighboring regions, or `None` if the step is impassable. Costs must not be negative.
+pub fn visit_with_cost<T, V, E, F>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    sources: &[RegionNodeIdx],
+    settings: &Settings<T>,
+    cost: F,
+) -> Result<DistanceField<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+    F: Fn(RegionNodeIdx, RegionNodeIdx, T) -> Option<T>,
+{
+    let count = region_graph.node_count();
+    let mut distances = vec![None; count];
+    let mut nearest = vec![None; count];
+    let mut queue = BinaryHeap::new();
+
+    for source in sources {
+        if source.index() >= count {
+            return Err(failure::format_err!("Source region {:?} is not in the graph", source));
+        }
+        distances[source.index()] = Some(T::zero());
+        nearest[source.index()] = Some(*source);
+        queue.push(MinScored(T::zero(), *source));
+    }
+
+    while let Some(MinScored(distance, region)) = queue.pop() {
+        // Skip stale entries left behind when a shorter path was found
+        if distances[region.index()].map_or(false, |best| distance > best) {
+            continue;
+        }
+
+        let position = region_graph[region].pos;
+        for neighbor in region_graph.neighbors(region) {
+            let length = T::from(nalgebra::distance(&position, &region_graph[neighbor].pos));
+            let step = match cost(region, neighbor, length) {
+                Some(step) if step >= T::zero() => step,
+                Some(_) => return Err(failure::format_err!("Negative cost from {:?} to {:?}", region, neighbor)),
+                None => continue,
+            };
+
+            let next = distance + step;
+            if settings.max_distance.map_or(false, |max| next > max) {
+                continue;
+            }
+            if distances[neighbor.index()].map_or(true, |best| next < best) {
+                distances[neighbor.index()] = Some(next);
+                nearest[neighbor.index()] = nearest[region.index()];
+                queue.push(MinScored(next, neighbor));
+            }
+        }
+    }
+
+    Ok(DistanceField { distances, nearest })
+}
+
+/// Distance from the coast, with the land and lake regions bordering the ocean as sources. Ocean regions are measured
+/// the same way, so the field grows in both directions from the shoreline.
+pub fn from_coast<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    water: &Water,
+    settings: &Settings<T>,
+) -> Result<DistanceField<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    let mut sources = water
+        .coast_edges()
+        .filter_map(|edge| region_graph.edge_endpoints(edge))
+        .flat_map(|(a, b)| vec![a, b])
+        .filter(|region| !water.is_ocean(*region))
+        .collect::<Vec<_>>();
+    sources.sort();
+    sources.dedup();
+
+    visit(region_graph, &sources, settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+    use crate::{water, HasElevation};
+    use rand::Rng;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+        distance: Option<f32>,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn distance_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        // Graph distance is never shorter than the straight line to the nearest source
+        let sources = (0..5)
+            .map(|_| RegionNodeIdx::new(rng.gen_range(0, region_graph.node_count())))
+            .collect::<Vec<_>>();
+        let field = visit(&region_graph, &sources, &Settings::<f32>::default()).unwrap();
+        for region in region_graph.node_indices() {
+            let nearest = field.nearest(region).unwrap();
+            assert!(sources.contains(&nearest));
+            let straight = nalgebra::distance(&region_graph[region].pos, &region_graph[nearest].pos);
+            assert!(field.distance(region).unwrap() >= straight - 1.0e-3);
+        }
+
+        let limited = visit(&region_graph, &sources, &Settings::<f32>::default().with_max_distance(Some(100.0))).unwrap();
+        assert!(region_graph.node_indices().any(|region| limited.distance(region).is_none()));
+        assert!(region_graph
+            .node_indices()
+            .all(|region| limited.distance(region).map_or(true, |distance| distance <= 100.0)));
+
+        // Blocking every step leaves only the sources reached
+        let blocked = visit_with_cost(&region_graph, &sources, &Settings::<f32>::default(), |_, _, _| None).unwrap();
+        assert!(region_graph
+            .node_indices()
+            .all(|region| blocked.distance(region).is_some() == sources.contains(&region)));
+
+        let water = water::visit(&region_graph, &water::Settings::<f32>::default()).unwrap();
+        let coast = from_coast(&region_graph, &water, &Settings::<f32>::default()).unwrap();
+        for edge in water.coast_edges() {
+            let (a, b) = region_graph.edge_endpoints(edge).unwrap();
+            let land = if water.is_ocean(a) { b } else { a };
+            assert!(coast.distance(land).unwrap().abs() < std::f32::EPSILON);
+        }
+
+        for region in region_graph.node_indices() {
+            region_graph[region].value.distance = coast.distance(region);
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let distance = region.value.distance.unwrap_or(0.0);
+            let shade = (255.0 - distance.min(255.0)) as u8;
+            if region.value.elevation() < 0.3 {
+                image::Rgb([0, 0, shade])
+            } else {
+                image::Rgb([0, shade, 0])
+            }
+        });
+
+        imgbuf.save("output/distance.png").unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use crate::HasValue;
 use nalgebra::{Point2, Vector2};
-use petgraph::{graph::NodeIndex, visit::EdgeRef};
+use petgraph::{graph::NodeIndex, visit::EdgeRef, EdgeType, Graph};
 use std::collections::HashMap;
 use voronoi::voronoi;
 
@@ -352,6 +352,10 @@ where
                 vor_diagram.halfedges[curr_edge].origin,
             );
             region_node.borders.push(border_idx);
+            let border_regions = &mut border_graph[border_idx].regions;
+            if !border_regions.contains(&region_node_idx) {
+                border_regions.push(region_node_idx);
+            }
             let next_idx = get_or_insert_border_node(
                 &mut border_node_map,
                 &mut border_graph,
@@ -421,6 +425,48 @@ where
     (region_graph, border_graph)
 }
 
+/// Returns true if the region lies on the edge of the generated area, meaning some of its borders are not shared with
+/// another region.
+pub fn is_boundary_region<T, E: EdgeType>(graph: &Graph<RegionNode<T>, RegionEdge, E>, region: RegionNodeIdx) -> bool {
+    let mut borders = graph[region].borders.clone();
+    borders.sort_unstable();
+    borders.dedup();
+
+    graph.neighbors(region).count() < borders.len()
+}
+
+/// Smallest and largest corner positions of the border graph, the area covered by the regions. `None` if the graph has
+/// no area.
+pub fn bounds<T, E: EdgeType>(border_graph: &Graph<BorderNode<T>, BorderEdge, E>) -> Option<(Point2<f32>, Point2<f32>)> {
+    let (low, high) = border_graph.raw_nodes().iter().fold(
+        (Point2::new(std::f32::MAX, std::f32::MAX), Point2::new(std::f32::MIN, std::f32::MIN)),
+        |(low, high), node| {
+            let pos = node.weight.pos;
+            (
+                Point2::new(low.x.min(pos.x), low.y.min(pos.y)),
+                Point2::new(high.x.max(pos.x), high.y.max(pos.y)),
+            )
+        },
+    );
+
+    if high.x > low.x && high.y > low.y {
+        Some((low, high))
+    } else {
+        None
+    }
+}
+
+/// Area of the polygon spanned by the borders of a region.
+pub fn region_area<T, B, E: EdgeType>(region: &RegionNode<T>, border_graph: &Graph<BorderNode<B>, BorderEdge, E>) -> f32 {
+    let corners = region.borders.iter().map(|border| border_graph[*border].pos).collect::<Vec<_>>();
+    let twice = corners
+        .iter()
+        .zip(corners.iter().cycle().skip(1))
+        .fold(0.0, |sum, (a, b)| sum + a.x * b.y - b.x * a.y);
+
+    twice.abs() / 2.0
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -488,6 +534,11 @@ pub(crate) mod tests {
 
         let (region_graph, border_graph) =
             gen_dual_graph::<(), (), rand_xorshift::XorShiftRng>(dims, 8000, 2, &mut rng);
+        let boundary = region_graph
+            .node_indices()
+            .filter(|idx| is_boundary_region(&region_graph, *idx))
+            .count();
+        assert!(boundary > 0 && boundary < region_graph.node_count() / 2);
         draw_graph(
             &mut imgbuf,
             &region_graph,
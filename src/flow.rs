@@ -0,0 +1,333 @@
+//! Flow
+//! Fills depressions with a priority flood seeded from the regions on the edge of the graph, so every region has a
+//! path downhill to the map boundary. Every region is then given a single downslope receiver and flow is accumulated
+//! from the highest regions down. The resulting `FlowRouting` is reused by the erosion, river and lake stages, and the
+//! same routing can be run over the corners of the border graph.
+//!
+use crate::{
+    dual_graph::{is_boundary_region, BorderEdge, BorderNode, RegionEdge, RegionNode, RegionNodeIdx},
+    scored::MinScored,
+    HasElevation,
+};
+use nalgebra::{Point2, RealField};
+use petgraph::{graph::NodeIndex, EdgeType, Graph};
+use std::collections::BinaryHeap;
+
+/// Single flow direction routing over a depression free surface.
+#[derive(Clone, Debug)]
+pub struct FlowRouting<T: RealField> {
+    receivers: Vec<Option<RegionNodeIdx>>,
+    order: Vec<RegionNodeIdx>,
+    filled: Vec<T>,
+    accumulation: Vec<T>,
+    depths: Option<Vec<T>>,
+}
+impl<T: RealField> FlowRouting<T> {
+    /// Region the water of `region` flows into, `None` for outlets on the map boundary.
+    pub fn receiver(&self, region: RegionNodeIdx) -> Option<RegionNodeIdx> {
+        self.receivers[region.index()]
+    }
+
+    /// Elevation of the region after depression filling.
+    pub fn filled(&self, region: RegionNodeIdx) -> T {
+        self.filled[region.index()]
+    }
+
+    /// Number of regions draining through the region, including itself.
+    pub fn accumulation(&self, region: RegionNodeIdx) -> T {
+        self.accumulation[region.index()]
+    }
+
+    /// Depth the region was filled by, `None` unless depths were kept.
+    pub fn depth(&self, region: RegionNodeIdx) -> Option<T> {
+        self.depths.as_ref().map(|depths| depths[region.index()])
+    }
+
+    /// Returns true if the region was raised to fill a depression, which makes it a lake candidate.
+    pub fn is_filled(&self, region: RegionNodeIdx) -> bool {
+        self.depth(region).map_or(false, |depth| depth > T::zero())
+    }
+
+    /// Every region, ordered so that each one comes before its receiver.
+    pub fn upstream_order(&self) -> impl DoubleEndedIterator<Item = RegionNodeIdx> + '_ {
+        self.order.iter().rev().copied()
+    }
+
+    /// Accumulates a per-region quantity, such as rainfall, downstream along the receivers.
+    pub fn accumulate(&self, weights: &[T]) -> Vec<T> {
+        let mut accumulation = weights.to_vec();
+        for region in self.upstream_order() {
+            if let Some(next) = self.receivers[region.index()] {
+                let upstream = accumulation[region.index()];
+                accumulation[next.index()] += upstream;
+            }
+        }
+        accumulation
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    epsilon: T,
+    keep_depths: bool,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Minimum elevation increase from a region to every region it drains, zero fills depressions flat.
+    pub fn with_epsilon(mut self, epsilon: T) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Keep how deep every region was filled, so filled pits can be turned into lakes.
+    pub fn with_keep_depths(mut self, keep_depths: bool) -> Self {
+        self.keep_depths = keep_depths;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            epsilon: 0.0.into(),
+            keep_depths: false,
+        }
+    }
+}
+
+/// Steepest downhill neighbor of a node and the slope towards it, `None` for pits.
+fn descent<N, W, E, T, P>(graph: &Graph<N, W, E>, elevations: &[T], node: NodeIndex, position: &P) -> Option<(NodeIndex, T)>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+    P: Fn(&N) -> Point2<f32>,
+{
+    let origin = position(&graph[node]);
+
+    graph
+        .neighbors(node)
+        .filter_map(|neighbor| {
+            let drop = elevations[node.index()] - elevations[neighbor.index()];
+            let distance = T::from(nalgebra::distance(&origin, &position(&graph[neighbor])));
+            if drop > T::zero() && distance > T::zero() {
+                Some((neighbor, drop / distance))
+            } else {
+                None
+            }
+        })
+        .fold(None, |acc: Option<(NodeIndex, T)>, (neighbor, slope)| match acc {
+            Some((_, best)) if best >= slope => acc,
+            _ => Some((neighbor, slope)),
+        })
+}
+
+/// Steepest downhill neighbor of a region and the slope towards it, `None` for pits.
+pub fn steepest_descent<T, V, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    elevations: &[T],
+    region: RegionNodeIdx,
+) -> Option<(RegionNodeIdx, T)>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    descent(region_graph, elevations, region, &|region: &RegionNode<V>| region.pos)
+}
+
+fn flood<N, W, E, T, P>(graph: &Graph<N, W, E>, elevations: &[T], outlets: &[bool], settings: &Settings<T>, position: P) -> FlowRouting<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+    P: Fn(&N) -> Point2<f32>,
+{
+    let count = graph.node_count();
+    let mut filled = elevations.to_vec();
+    let mut parents = vec![None; count];
+    let mut visited = vec![false; count];
+    let mut order = Vec::with_capacity(count);
+    let mut queue = BinaryHeap::with_capacity(count);
+
+    for node in graph.node_indices() {
+        if outlets[node.index()] {
+            visited[node.index()] = true;
+            queue.push(MinScored(filled[node.index()], node));
+        }
+    }
+    if queue.is_empty() {
+        let lowest = graph.node_indices().fold(None, |acc: Option<NodeIndex>, node| match acc {
+            Some(lowest) if elevations[lowest.index()] <= elevations[node.index()] => acc,
+            _ => Some(node),
+        });
+        if let Some(lowest) = lowest {
+            visited[lowest.index()] = true;
+            queue.push(MinScored(filled[lowest.index()], lowest));
+        }
+    }
+
+    // Priority flood, every node is raised to at least the lowest level it can spill over towards an outlet
+    while let Some(MinScored(level, node)) = queue.pop() {
+        order.push(node);
+
+        for neighbor in graph.neighbors(node) {
+            if visited[neighbor.index()] {
+                continue;
+            }
+            visited[neighbor.index()] = true;
+            parents[neighbor.index()] = Some(node);
+
+            let raised = filled[neighbor.index()].max(level + settings.epsilon);
+            filled[neighbor.index()] = raised;
+            queue.push(MinScored(raised, neighbor));
+        }
+    }
+    // Nodes that cannot reach an outlet keep their elevation and drain nowhere
+    for node in graph.node_indices() {
+        if !visited[node.index()] {
+            order.push(node);
+        }
+    }
+
+    // Nodes on flats have no lower neighbor, so they drain towards the node that flooded them instead
+    let receivers = graph
+        .node_indices()
+        .map(|node| match parents[node.index()] {
+            Some(parent) => descent(graph, &filled, node, &position).map(|(next, _)| next).or(Some(parent)),
+            None => None,
+        })
+        .collect::<Vec<_>>();
+
+    let depths = if settings.keep_depths {
+        Some(filled.iter().zip(elevations).map(|(filled, original)| *filled - *original).collect())
+    } else {
+        None
+    };
+
+    let mut routing = FlowRouting {
+        receivers,
+        order,
+        filled,
+        accumulation: Vec::new(),
+        depths,
+    };
+    routing.accumulation = routing.accumulate(&vec![T::one(); count]);
+    routing
+}
+
+/// Routes flow over the given elevations, indexed by region, without modifying the graph. Regions on the edge of the
+/// graph are the outlets.
+pub fn route<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, elevations: &[T], settings: &Settings<T>) -> FlowRouting<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    let outlets = region_graph
+        .node_indices()
+        .map(|region| is_boundary_region(region_graph, region))
+        .collect::<Vec<_>>();
+
+    flood(region_graph, elevations, &outlets, settings, |region| region.pos)
+}
+
+/// Routes flow between the corners of the border graph, given an elevation and whether it is an outlet for every
+/// corner. The returned routing is indexed by border node.
+pub fn route_borders<T, B, E>(
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    elevations: &[T],
+    outlets: &[bool],
+    settings: &Settings<T>,
+) -> FlowRouting<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    flood(border_graph, elevations, outlets, settings, |border| border.pos)
+}
+
+/// Fills all depressions in the graph, writing the filled elevations back, and returns the flow routing over them.
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<FlowRouting<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.epsilon < T::zero() {
+        return Err(failure::format_err!("Depression filling epsilon must not be negative"));
+    }
+
+    let elevations = region_graph.node_weights_mut().map(|region| region.value.elevation()).collect::<Vec<_>>();
+    let routing = route(region_graph, &elevations, settings);
+
+    for (region, filled) in region_graph.node_weights_mut().zip(&routing.filled) {
+        region.value.set_elevation(*filled);
+    }
+
+    Ok(routing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn flow_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        let settings = Settings::<f32>::default().with_epsilon(1.0e-5).with_keep_depths(true);
+        let routing = visit(&mut region_graph, &settings).unwrap();
+
+        let mut seen = vec![false; region_graph.node_count()];
+        for region in routing.upstream_order() {
+            seen[region.index()] = true;
+            match routing.receiver(region) {
+                Some(next) => {
+                    assert!(!seen[next.index()]);
+                    assert!(routing.filled(next) < routing.filled(region));
+                }
+                None => assert!(is_boundary_region(&region_graph, region)),
+            }
+            assert!(routing.depth(region).unwrap() >= 0.0);
+        }
+        let outlets = region_graph
+            .node_indices()
+            .filter(|region| routing.receiver(*region).is_none())
+            .map(|region| routing.accumulation(region))
+            .sum::<f32>();
+        assert!((outlets - region_graph.node_count() as f32).abs() < 0.5);
+
+        let max_accumulation = region_graph.node_indices().map(|region| routing.accumulation(region)).fold(0.0, f32::max);
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let elevation = (200.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([elevation, elevation, elevation])
+        });
+        for region in region_graph.node_indices() {
+            if let Some(next) = routing.receiver(region) {
+                let flux = (routing.accumulation(region) / max_accumulation).sqrt();
+                if flux > 0.05 {
+                    let (from, to) = (region_graph[region].pos, region_graph[next].pos);
+                    imageproc::drawing::draw_antialiased_line_segment_mut(
+                        &mut imgbuf,
+                        (from.x as i32, from.y as i32),
+                        (to.x as i32, to.y as i32),
+                        image::Rgb([0, 0, (100.0 + 155.0 * flux) as u8]),
+                        imageproc::pixelops::interpolate,
+                    );
+                }
+            }
+        }
+
+        imgbuf.save("output/flow.png").unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+//! Heightmap
+//! Imports a painted grayscale heightmap, 8-bit or 16-bit, as region elevation. The image is stretched over the bounds
+//! of the border graph and sampled either at the position of every region or averaged over the pixels inside its
+//! polygon. Pixel values are remapped into an elevation range and blended with the current elevation, so the
+//! procedural stages can refine painted terrain instead of replacing it.
+//!
+use crate::{
+    dual_graph::{bounds, BorderEdge, BorderNode, RegionEdge, RegionNode},
+    Blend, HasElevation,
+};
+use image::{ImageBuffer, Luma, Primitive};
+use nalgebra::{Point2, RealField};
+use petgraph::{EdgeType, Graph};
+use std::ops::Deref;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Sampling {
+    /// Bilinear sample at the region position.
+    Point,
+    /// Mean of the pixels whose centers lie inside the region polygon, regions smaller than a pixel fall back to
+    /// `Point`.
+    Area,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sampling: Sampling,
+    low: T,
+    high: T,
+    exponent: T,
+    blend: Blend,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Elevations black and white pixels are mapped to.
+    pub fn with_range(mut self, low: T, high: T) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    /// Exponent applied to the pixel value within `[0, 1]` before it is mapped to the range.
+    pub fn with_exponent(mut self, exponent: T) -> Self {
+        self.exponent = exponent;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: Blend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sampling: Sampling::Point,
+            low: 0.0.into(),
+            high: 1.0.into(),
+            exponent: 1.0.into(),
+            blend: Blend::Replace,
+        }
+    }
+}
+
+/// Pixel value within `[0, 1]`.
+fn pixel<S, C>(image: &ImageBuffer<Luma<S>, C>, x: u32, y: u32) -> f64
+where
+    S: Primitive + 'static,
+    C: Deref<Target = [S]>,
+{
+    let value = num::cast::<S, f64>(image.get_pixel(x, y).0[0]).unwrap_or(0.0);
+    let max = num::cast::<S, f64>(S::max_value()).unwrap_or(1.0);
+    value / max
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn bilinear<S, C>(image: &ImageBuffer<Luma<S>, C>, x: f64, y: f64) -> f64
+where
+    S: Primitive + 'static,
+    C: Deref<Target = [S]>,
+{
+    let (width, height) = image.dimensions();
+    // Pixel centers sit at half coordinates
+    let x = (x - 0.5).max(0.0).min(f64::from(width - 1));
+    let y = (y - 0.5).max(0.0).min(f64::from(height - 1));
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+
+    let top = pixel(image, x0, y0) * (1.0 - fx) + pixel(image, x1, y0) * fx;
+    let bottom = pixel(image, x0, y1) * (1.0 - fx) + pixel(image, x1, y1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Mean pixel value inside a convex polygon given in pixel coordinates, `None` if no pixel center is inside.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn area<S, C>(image: &ImageBuffer<Luma<S>, C>, polygon: &[Point2<f64>]) -> Option<f64>
+where
+    S: Primitive + 'static,
+    C: Deref<Target = [S]>,
+{
+    let (width, height) = image.dimensions();
+    let (low, high) = polygon.iter().fold(
+        (Point2::new(std::f64::MAX, std::f64::MAX), Point2::new(std::f64::MIN, std::f64::MIN)),
+        |(low, high), point| {
+            (
+                Point2::new(low.x.min(point.x), low.y.min(point.y)),
+                Point2::new(high.x.max(point.x), high.y.max(point.y)),
+            )
+        },
+    );
+
+    let inside = |x: f64, y: f64| {
+        polygon.iter().zip(polygon.iter().cycle().skip(1)).all(|(a, b)| {
+            let cross = (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
+            cross >= 0.0
+        })
+    };
+
+    let (mut sum, mut count) = (0.0, 0);
+    for y in (low.y.max(0.0) as u32)..(high.y.ceil().max(0.0) as u32).min(height) {
+        for x in (low.x.max(0.0) as u32)..(high.x.ceil().max(0.0) as u32).min(width) {
+            if inside(f64::from(x) + 0.5, f64::from(y) + 0.5) {
+                sum += pixel(image, x, y);
+                count += 1;
+            }
+        }
+    }
+
+    if count > 0 {
+        Some(sum / f64::from(count))
+    } else {
+        None
+    }
+}
+
+pub fn visit<T, V, B, E, S, C>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    image: &ImageBuffer<Luma<S>, C>,
+    settings: &Settings<T>,
+) -> Result<(), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+    S: Primitive + 'static,
+    C: Deref<Target = [S]>,
+{
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(failure::format_err!("Heightmap is empty"));
+    }
+
+    // The border graph is clipped to the map, so its corners give the area the image is stretched over
+    let (low, high) = bounds(border_graph).ok_or_else(|| failure::format_err!("Border graph has no area to place the heightmap over"))?;
+    let scale = (
+        f64::from(width) / f64::from(high.x - low.x),
+        f64::from(height) / f64::from(high.y - low.y),
+    );
+    let to_pixel = |point: Point2<f32>| Point2::new(f64::from(point.x - low.x) * scale.0, f64::from(point.y - low.y) * scale.1);
+
+    let mut polygon = Vec::new();
+    for region in region_graph.node_weights_mut() {
+        let center = to_pixel(region.pos);
+        let sampled = match settings.sampling {
+            Sampling::Point => None,
+            Sampling::Area => {
+                polygon.clear();
+                polygon.extend(region.borders.iter().map(|border| to_pixel(border_graph[*border].pos)));
+                polygon.sort_by(|a, b| {
+                    let (a, b) = ((a.y - center.y).atan2(a.x - center.x), (b.y - center.y).atan2(b.x - center.x));
+                    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                });
+                polygon.dedup();
+                if polygon.len() >= 3 {
+                    area(image, &polygon)
+                } else {
+                    None
+                }
+            }
+        };
+        let value = sampled.unwrap_or_else(|| bilinear(image, center.x, center.y));
+
+        let value = nalgebra::convert::<f64, T>(value).powf(settings.exponent);
+        let value = settings.low + (settings.high - settings.low) * value;
+        let elevation = settings.blend.apply(region.value.elevation(), value);
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn heightmap_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::test_graph::<TestInner>();
+
+        // Left to right ramp, in both bit depths
+        let gray = image::GrayImage::from_fn(256, 256, |x, _| image::Luma([x as u8]));
+        let wide = image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_fn(256, 256, |x, _| image::Luma([(x * 257) as u16]));
+
+        visit(&mut region_graph, &border_graph, &gray, &Settings::<f32>::default()).unwrap();
+        for region in region_graph.node_weights_mut() {
+            assert!((region.value.elevation() - region.pos.x / 1024.0).abs() < 0.02);
+        }
+
+        let settings = Settings::<f32>::default().with_sampling(Sampling::Area).with_range(0.2, 0.6);
+        visit(&mut region_graph, &border_graph, &wide, &settings).unwrap();
+        for region in region_graph.node_weights_mut() {
+            let elevation = region.value.elevation();
+            assert!((0.2..=0.6).contains(&elevation));
+            assert!((elevation - (0.2 + 0.4 * region.pos.x / 1024.0)).abs() < 0.02);
+        }
+
+        // Multiplying by a white image leaves the terrain untouched
+        let before = region_graph.node_weights_mut().map(|region| region.value.elevation()).collect::<Vec<_>>();
+        let white = image::GrayImage::from_pixel(16, 16, image::Luma([255]));
+        visit(
+            &mut region_graph,
+            &border_graph,
+            &white,
+            &Settings::<f32>::default().with_blend(Blend::Multiply),
+        )
+        .unwrap();
+        for (region, before) in region_graph.node_weights_mut().zip(before) {
+            assert!((region.value.elevation() - before).abs() < std::f32::EPSILON);
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/heightmap.png").unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! Hydraulic Erosion
+//! Visits the graph for a number of iterations, routing rain water from every region to its steepest downhill
+//! neighbor. Water moving downhill picks up sediment until it reaches its carrying capacity, which grows with the water
+//! volume and the slope, and drops sediment again where it slows down or pools in a pit.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode},
+    flow::steepest_descent,
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{graph::NodeIndex, EdgeType, Graph};
+
+/// Total material removed from and added to every region over all iterations.
+#[derive(Clone, Debug)]
+pub struct Erosion<T: RealField> {
+    eroded: Vec<T>,
+    deposited: Vec<T>,
+}
+impl<T: RealField> Erosion<T> {
+    pub fn eroded(&self, region: NodeIndex) -> T {
+        self.eroded[region.index()]
+    }
+
+    pub fn deposited(&self, region: NodeIndex) -> T {
+        self.deposited[region.index()]
+    }
+
+    /// Net elevation change of the region, negative where material was removed.
+    pub fn net(&self, region: NodeIndex) -> T {
+        self.deposited(region) - self.eroded(region)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    iterations: usize,
+    rain: T,
+    capacity: T,
+    erosion: T,
+    deposition: T,
+    max_erosion: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Water added to every region per iteration.
+    pub fn with_rain(mut self, rain: T) -> Self {
+        self.rain = rain;
+        self
+    }
+
+    /// Sediment carried per unit of water and unit of slope.
+    pub fn with_capacity(mut self, capacity: T) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Fraction of the remaining capacity picked up from a region.
+    pub fn with_erosion(mut self, erosion: T) -> Self {
+        self.erosion = erosion;
+        self
+    }
+
+    /// Fraction of the excess sediment dropped on a region.
+    pub fn with_deposition(mut self, deposition: T) -> Self {
+        self.deposition = deposition;
+        self
+    }
+
+    /// Upper bound on the material removed from a single region per iteration.
+    pub fn with_max_erosion(mut self, max_erosion: T) -> Self {
+        self.max_erosion = max_erosion;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            iterations: 50,
+            rain: 1.0.into(),
+            capacity: 0.01.into(),
+            erosion: 0.3.into(),
+            deposition: 0.3.into(),
+            max_erosion: 0.01.into(),
+        }
+    }
+}
+
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Erosion<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    E: EdgeType,
+{
+    let count = region_graph.node_count();
+    let mut elevations = region_graph.node_weights_mut().map(|region| region.value.elevation()).collect::<Vec<_>>();
+    let mut eroded = vec![T::zero(); count];
+    let mut deposited = vec![T::zero(); count];
+
+    let mut order = region_graph.node_indices().collect::<Vec<_>>();
+    let mut water = vec![T::zero(); count];
+    let mut sediment = vec![T::zero(); count];
+
+    for _ in 0..settings.iterations {
+        // Highest regions first, so every region has received all of its upstream water before passing it on
+        order.sort_unstable_by(|a, b| {
+            elevations[b.index()]
+                .partial_cmp(&elevations[a.index()])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        for (water, sediment) in water.iter_mut().zip(sediment.iter_mut()) {
+            *water = settings.rain;
+            *sediment = T::zero();
+        }
+        // Receivers are fixed for the iteration, so material is never passed to a region that was already visited
+        let descents = region_graph
+            .node_indices()
+            .map(|region| steepest_descent(region_graph, &elevations, region))
+            .collect::<Vec<_>>();
+
+        for &region in &order {
+            let i = region.index();
+
+            if let Some((next, slope)) = descents[i] {
+                let capacity = settings.capacity * water[i] * slope;
+
+                if sediment[i] > capacity {
+                    let amount = settings.deposition * (sediment[i] - capacity);
+                    elevations[i] += amount;
+                    deposited[i] += amount;
+                    sediment[i] -= amount;
+                } else {
+                    // Never dig below the receiver, that would turn the region into a pit
+                    let amount = (settings.erosion * (capacity - sediment[i]))
+                        .min(settings.max_erosion)
+                        .min(elevations[i] - elevations[next.index()])
+                        .max(T::zero());
+                    elevations[i] -= amount;
+                    eroded[i] += amount;
+                    sediment[i] += amount;
+                }
+
+                let (carried_water, carried_sediment) = (water[i], sediment[i]);
+                water[next.index()] += carried_water;
+                sediment[next.index()] += carried_sediment;
+            } else {
+                // Pits are filled up to their lowest neighbor, the rest is spread evenly over the pit and that neighbor
+                // so the filled pit stays level and no sediment is lost
+                let spill = region_graph
+                    .neighbors(region)
+                    .fold(None, |lowest: Option<NodeIndex>, neighbor| match lowest {
+                        Some(lowest) if elevations[lowest.index()] <= elevations[neighbor.index()] => Some(lowest),
+                        _ => Some(neighbor),
+                    });
+                if let Some(spill) = spill {
+                    let fill = (elevations[spill.index()] - elevations[i]).max(T::zero()).min(sediment[i]);
+                    let excess = (sediment[i] - fill) * T::from(0.5);
+                    elevations[i] += fill + excess;
+                    deposited[i] += fill + excess;
+                    elevations[spill.index()] += excess;
+                    deposited[spill.index()] += excess;
+                } else {
+                    elevations[i] += sediment[i];
+                    deposited[i] += sediment[i];
+                }
+            }
+        }
+    }
+
+    for (region, elevation) in region_graph.node_weights_mut().zip(elevations) {
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(Erosion { eroded, deposited })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn hydraulic_erosion_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        let before = region_graph
+            .node_indices()
+            .map(|idx| region_graph[idx].value.elevation())
+            .collect::<Vec<_>>();
+
+        let erosion = visit(&mut region_graph, &Settings::<f32>::default()).unwrap();
+
+        let (mut total_eroded, mut total_deposited) = (0.0, 0.0);
+        for idx in region_graph.node_indices() {
+            let change = region_graph[idx].value.elevation() - before[idx.index()];
+            assert!((change - erosion.net(idx)).abs() < 1.0e-4);
+            total_eroded += erosion.eroded(idx);
+            total_deposited += erosion.deposited(idx);
+        }
+        // Every region drains into a pit within an iteration, so all eroded material is deposited again
+        assert!(total_eroded > 0.0);
+        assert!((total_deposited - total_eroded).abs() <= total_eroded * 1.0e-3);
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/hydraulic_erosion.png").unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Köppen
+//! Köppen–Geiger climate classification from the monthly statistics of the seasonal simulation. The thresholds follow
+//! Peel et al. (2007): the warmest month separates polar climates, a precipitation threshold depending on the annual
+//! mean temperature and the rainy season separates arid climates, and the coldest month separates tropical, temperate
+//! and continental climates, which are refined by their dry season and summer heat.
+//!
+use crate::{
+    dual_graph::{region_area, BorderEdge, BorderNode, RegionEdge, RegionNode, RegionNodeIdx},
+    seasons::{Month, Seasons, MONTHS},
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Climate {
+    Af,
+    Am,
+    Aw,
+    As,
+    BWh,
+    BWk,
+    BSh,
+    BSk,
+    Csa,
+    Csb,
+    Csc,
+    Cwa,
+    Cwb,
+    Cwc,
+    Cfa,
+    Cfb,
+    Cfc,
+    Dsa,
+    Dsb,
+    Dsc,
+    Dsd,
+    Dwa,
+    Dwb,
+    Dwc,
+    Dwd,
+    Dfa,
+    Dfb,
+    Dfc,
+    Dfd,
+    ET,
+    EF,
+}
+impl Climate {
+    pub fn code(self) -> &'static str {
+        match self {
+            Climate::Af => "Af",
+            Climate::Am => "Am",
+            Climate::Aw => "Aw",
+            Climate::As => "As",
+            Climate::BWh => "BWh",
+            Climate::BWk => "BWk",
+            Climate::BSh => "BSh",
+            Climate::BSk => "BSk",
+            Climate::Csa => "Csa",
+            Climate::Csb => "Csb",
+            Climate::Csc => "Csc",
+            Climate::Cwa => "Cwa",
+            Climate::Cwb => "Cwb",
+            Climate::Cwc => "Cwc",
+            Climate::Cfa => "Cfa",
+            Climate::Cfb => "Cfb",
+            Climate::Cfc => "Cfc",
+            Climate::Dsa => "Dsa",
+            Climate::Dsb => "Dsb",
+            Climate::Dsc => "Dsc",
+            Climate::Dsd => "Dsd",
+            Climate::Dwa => "Dwa",
+            Climate::Dwb => "Dwb",
+            Climate::Dwc => "Dwc",
+            Climate::Dwd => "Dwd",
+            Climate::Dfa => "Dfa",
+            Climate::Dfb => "Dfb",
+            Climate::Dfc => "Dfc",
+            Climate::Dfd => "Dfd",
+            Climate::ET => "ET",
+            Climate::EF => "EF",
+        }
+    }
+
+    /// Main group, one of `A`, `B`, `C`, `D` and `E`.
+    pub fn group(self) -> char {
+        self.code().chars().next().expect("Climate codes are not empty")
+    }
+}
+impl fmt::Display for Climate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Season with the least precipitation, the second letter of temperate and continental climates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DrySeason {
+    Summer,
+    Winter,
+    None,
+}
+
+/// Summer heat, the third letter of temperate and continental climates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Heat {
+    Hot,
+    Warm,
+    Cold,
+    Severe,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: T,
+    coldest_month: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Coldest month temperature separating temperate from continental climates, 0 after Peel et al. or -3 as in
+    /// Köppen's original scheme.
+    pub fn with_coldest_month(mut self, coldest_month: T) -> Self {
+        self.coldest_month = coldest_month;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: 0.3.into(),
+            coldest_month: 0.0.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Classification {
+    climates: Vec<Option<Climate>>,
+    areas: Vec<(Climate, f32)>,
+}
+impl Classification {
+    /// Climate of a land region, `None` for regions below sea level.
+    pub fn climate(&self, region: RegionNodeIdx) -> Option<Climate> {
+        self.climates[region.index()]
+    }
+
+    /// Land area of every climate that occurs, largest first.
+    pub fn summary(&self) -> &[(Climate, f32)] {
+        &self.areas
+    }
+
+    pub fn area(&self, climate: Climate) -> f32 {
+        self.areas.iter().find(|(other, _)| *other == climate).map_or(0.0, |(_, area)| *area)
+    }
+
+    pub fn land_area(&self) -> f32 {
+        self.areas.iter().map(|(_, area)| area).sum()
+    }
+}
+
+/// Classifies a year of monthly means, starting in January. Temperatures are in degrees Celsius and precipitation in
+/// millimeters, the hemisphere is taken from which half of the year is warmer.
+#[allow(clippy::too_many_lines, clippy::cast_precision_loss)]
+pub fn classify<T: RealField + From<f32>>(months: &[Month<T>; MONTHS], settings: &Settings<T>) -> Climate {
+    let temperature = months.iter().fold(T::zero(), |sum, month| sum + month.temperature) / T::from(MONTHS as f32);
+    let precipitation = months.iter().fold(T::zero(), |sum, month| sum + month.precipitation);
+    let warmest = months.iter().fold(months[0].temperature, |warmest, month| warmest.max(month.temperature));
+    let coldest = months.iter().fold(months[0].temperature, |coldest, month| coldest.min(month.temperature));
+
+    if warmest < T::from(10.0) {
+        return if warmest > T::zero() { Climate::ET } else { Climate::EF };
+    }
+
+    // Summer is the warmer half of the year, April to September in the north
+    let april_to_september = months[3..9].iter().fold(T::zero(), |sum, month| sum + month.temperature);
+    let northern = april_to_september * T::from(2.0) >= temperature * T::from(MONTHS as f32);
+    let summer = |month: usize| (3..9).contains(&month) == northern;
+    let half = |in_summer: bool| {
+        let mut rain = months
+            .iter()
+            .enumerate()
+            .filter(|(month, _)| summer(*month) == in_summer)
+            .map(|(_, month)| month.precipitation);
+        let first = rain.next().expect("Half a year has months");
+        rain.fold((first, first, first), |(total, driest, wettest), rain| {
+            (total + rain, driest.min(rain), wettest.max(rain))
+        })
+    };
+    let (summer_total, summer_driest, summer_wettest) = half(true);
+    let (winter_total, winter_driest, winter_wettest) = half(false);
+
+    let dryness = if winter_total >= precipitation * T::from(0.7) {
+        T::zero()
+    } else if summer_total >= precipitation * T::from(0.7) {
+        T::from(280.0)
+    } else {
+        T::from(140.0)
+    };
+    let threshold = temperature * T::from(20.0) + dryness;
+    if precipitation < threshold {
+        let hot = temperature >= T::from(18.0);
+        return match (precipitation < threshold * T::from(0.5), hot) {
+            (true, true) => Climate::BWh,
+            (true, false) => Climate::BWk,
+            (false, true) => Climate::BSh,
+            (false, false) => Climate::BSk,
+        };
+    }
+
+    if coldest >= T::from(18.0) {
+        let (driest_month, driest) = months
+            .iter()
+            .enumerate()
+            .fold((0, months[0].precipitation), |(driest_month, driest), (month, statistics)| {
+                if statistics.precipitation < driest {
+                    (month, statistics.precipitation)
+                } else {
+                    (driest_month, driest)
+                }
+            });
+        return if driest >= T::from(60.0) {
+            Climate::Af
+        } else if driest >= T::from(100.0) - precipitation / T::from(25.0) {
+            Climate::Am
+        } else if summer(driest_month) {
+            Climate::As
+        } else {
+            Climate::Aw
+        };
+    }
+
+    let dry_season = if summer_driest < winter_driest && winter_wettest > summer_driest * T::from(3.0) && summer_driest < T::from(40.0) {
+        DrySeason::Summer
+    } else if winter_driest < summer_driest && summer_wettest > winter_driest * T::from(10.0) {
+        DrySeason::Winter
+    } else {
+        DrySeason::None
+    };
+    let warm_months = months.iter().filter(|month| month.temperature >= T::from(10.0)).count();
+    let heat = if warmest >= T::from(22.0) {
+        Heat::Hot
+    } else if warm_months >= 4 {
+        Heat::Warm
+    } else if coldest < T::from(-38.0) {
+        Heat::Severe
+    } else {
+        Heat::Cold
+    };
+
+    if coldest > settings.coldest_month {
+        // Winters this mild are never severe
+        match (dry_season, heat) {
+            (DrySeason::Summer, Heat::Hot) => Climate::Csa,
+            (DrySeason::Summer, Heat::Warm) => Climate::Csb,
+            (DrySeason::Summer, Heat::Cold | Heat::Severe) => Climate::Csc,
+            (DrySeason::Winter, Heat::Hot) => Climate::Cwa,
+            (DrySeason::Winter, Heat::Warm) => Climate::Cwb,
+            (DrySeason::Winter, Heat::Cold | Heat::Severe) => Climate::Cwc,
+            (DrySeason::None, Heat::Hot) => Climate::Cfa,
+            (DrySeason::None, Heat::Warm) => Climate::Cfb,
+            (DrySeason::None, Heat::Cold | Heat::Severe) => Climate::Cfc,
+        }
+    } else {
+        match (dry_season, heat) {
+            (DrySeason::Summer, Heat::Hot) => Climate::Dsa,
+            (DrySeason::Summer, Heat::Warm) => Climate::Dsb,
+            (DrySeason::Summer, Heat::Cold) => Climate::Dsc,
+            (DrySeason::Summer, Heat::Severe) => Climate::Dsd,
+            (DrySeason::Winter, Heat::Hot) => Climate::Dwa,
+            (DrySeason::Winter, Heat::Warm) => Climate::Dwb,
+            (DrySeason::Winter, Heat::Cold) => Climate::Dwc,
+            (DrySeason::Winter, Heat::Severe) => Climate::Dwd,
+            (DrySeason::None, Heat::Hot) => Climate::Dfa,
+            (DrySeason::None, Heat::Warm) => Climate::Dfb,
+            (DrySeason::None, Heat::Cold) => Climate::Dfc,
+            (DrySeason::None, Heat::Severe) => Climate::Dfd,
+        }
+    }
+}
+
+/// Classifies every land region from the monthly statistics of `seasons::visit` and sums the area of each climate.
+pub fn visit<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    seasons: &Seasons<T>,
+    settings: &Settings<T>,
+) -> Result<Classification, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    if seasons.region_count() != region_graph.node_count() {
+        return Err(failure::format_err!("Seasons were simulated for a different region graph"));
+    }
+
+    let mut areas: Vec<(Climate, f32)> = Vec::new();
+    let climates = region_graph
+        .node_indices()
+        .map(|region| {
+            let node = &region_graph[region];
+            if node.value.elevation() < settings.sea_level {
+                return None;
+            }
+
+            let climate = classify(seasons.months(region), settings);
+            let area = region_area(node, border_graph);
+            match areas.iter_mut().find(|(other, _)| *other == climate) {
+                Some((_, total)) => *total += area,
+                None => areas.push((climate, area)),
+            }
+            Some(climate)
+        })
+        .collect();
+    areas.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    Ok(Classification { climates, areas })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, tests::ClimateInner};
+    use crate::simple_wind::{self, Circulation};
+    use crate::{moisture, seasons, temperature};
+    use nalgebra::Vector2;
+
+    type TestInner = ClimateInner<Option<Climate>>;
+
+    fn station(temperatures: [f32; MONTHS], precipitation: [f32; MONTHS]) -> [Month<f32>; MONTHS] {
+        let mut months = [Month {
+            temperature: 0.0,
+            precipitation: 0.0,
+            wind: Vector2::zeros(),
+        }; MONTHS];
+        for (month, statistics) in months.iter_mut().enumerate() {
+            statistics.temperature = temperatures[month];
+            statistics.precipitation = precipitation[month];
+        }
+        months
+    }
+
+    fn color(climate: Climate) -> image::Rgb<u8> {
+        let shade = |base: [u8; 3]| {
+            let letters = climate.code().bytes().skip(1).fold(0_u8, u8::wrapping_add);
+            image::Rgb([
+                base[0].saturating_sub(letters % 5 * 20),
+                base[1].saturating_sub(letters % 7 * 15),
+                base[2],
+            ])
+        };
+        match climate.group() {
+            'A' => shade([40, 60, 250]),
+            'B' => shade([250, 200, 80]),
+            'C' => shade([120, 250, 80]),
+            'D' => shade([200, 60, 250]),
+            _ => shade([200, 220, 220]),
+        }
+    }
+
+    #[test]
+    fn koppen_stations() {
+        let settings = Settings::<f32>::default();
+        let singapore = station(
+            [26.5, 27.1, 27.5, 28.0, 28.3, 28.3, 27.9, 27.9, 27.6, 27.6, 27.0, 26.4],
+            [234.0, 114.0, 176.0, 154.0, 171.0, 140.0, 154.0, 150.0, 172.0, 170.0, 256.0, 317.0],
+        );
+        let darwin = station(
+            [28.5, 28.3, 28.4, 28.0, 26.5, 25.0, 24.8, 25.6, 27.5, 29.0, 29.5, 29.0],
+            [420.0, 375.0, 315.0, 100.0, 20.0, 2.0, 1.0, 5.0, 15.0, 70.0, 140.0, 250.0],
+        );
+        let cairo = station(
+            [14.0, 15.5, 18.0, 21.5, 25.0, 27.5, 28.5, 28.5, 26.5, 24.0, 19.5, 15.5],
+            [5.0, 4.0, 4.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 6.0],
+        );
+        let rome = station(
+            [7.5, 8.5, 10.5, 13.0, 17.0, 21.0, 24.0, 24.0, 21.0, 16.5, 12.0, 8.5],
+            [67.0, 73.0, 58.0, 81.0, 53.0, 34.0, 19.0, 37.0, 73.0, 113.0, 115.0, 81.0],
+        );
+        let london = station(
+            [5.2, 5.3, 7.6, 9.9, 13.3, 16.5, 18.7, 18.5, 15.7, 12.0, 8.0, 5.5],
+            [55.0, 41.0, 42.0, 44.0, 49.0, 45.0, 45.0, 50.0, 49.0, 69.0, 59.0, 55.0],
+        );
+        let moscow = station(
+            [-6.5, -6.7, -1.0, 6.5, 13.5, 17.0, 19.2, 17.0, 11.3, 5.5, -0.5, -4.5],
+            [52.0, 41.0, 35.0, 37.0, 49.0, 80.0, 85.0, 82.0, 68.0, 71.0, 55.0, 52.0],
+        );
+        let utqiagvik = station(
+            [-25.0, -26.5, -25.0, -17.0, -6.0, 2.0, 5.0, 3.5, -1.0, -9.5, -18.5, -23.0],
+            [3.0, 3.0, 3.0, 3.0, 3.0, 8.0, 25.0, 25.0, 15.0, 10.0, 5.0, 4.0],
+        );
+
+        assert_eq!(classify(&singapore, &settings), Climate::Af);
+        assert_eq!(classify(&darwin, &settings), Climate::Aw);
+        assert_eq!(classify(&cairo, &settings), Climate::BWh);
+        assert_eq!(classify(&rome, &settings), Climate::Csa);
+        assert_eq!(classify(&london, &settings), Climate::Cfb);
+        assert_eq!(classify(&moscow, &settings), Climate::Dfb);
+        assert_eq!(classify(&utqiagvik, &settings), Climate::ET);
+        assert_eq!(Climate::Dfb.to_string(), "Dfb");
+        assert_eq!(Climate::BWh.group(), 'B');
+    }
+
+    #[test]
+    pub fn koppen_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        let seasons = seasons::visit(
+            &mut region_graph,
+            &wind,
+            &temperature::Settings::default(),
+            &moisture::Settings::default(),
+            None,
+            &seasons::Settings::default().with_step(365.0 / 12.0),
+            &mut rng,
+        )
+        .unwrap();
+
+        let classification = visit(&region_graph, &border_graph, &seasons, &Settings::default()).unwrap();
+        let mut land_area = 0.0;
+        for region in region_graph.node_indices() {
+            region_graph[region].value.data = classification.climate(region);
+            let node = &region_graph[region];
+            match classification.climate(region) {
+                Some(climate) => {
+                    land_area += region_area(node, &border_graph);
+                    assert_eq!(climate, classify(seasons.months(region), &Settings::default()));
+                }
+                None => assert!(node.value.elevation < 0.3),
+            }
+        }
+
+        let summary = classification.summary();
+        assert!(summary.len() > 1);
+        assert!(summary.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!((classification.land_area() - land_area).abs() < land_area * 1.0e-4);
+        assert!((classification.area(summary[0].0) - summary[0].1).abs() < std::f32::EPSILON);
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            region.value.data.map_or(image::Rgb([0, 0, 128]), color)
+        });
+
+        imgbuf.save("output/koppen.png").unwrap();
+    }
+}
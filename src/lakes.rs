@@ -0,0 +1,306 @@
+//! Lakes
+//! Finds the closed depressions of the region graph and keeps them as lakes instead of filling them away. Every basin
+//! is filled flat to the elevation of its spill point, and its regions are given a lake ID, a surface elevation and a
+//! depth. Lakes that receive more water than they evaporate drain through a single outflow region, the others are
+//! endorheic and have no outflow. Basins filled no higher than the sea level are under the ocean and are left out.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    flow, HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug)]
+pub struct Lake<T: RealField> {
+    pub regions: Vec<RegionNodeIdx>,
+    /// Elevation of the water surface, the elevation of the spill point.
+    pub surface: T,
+    /// Region outside the lake the water spills into, `None` for endorheic lakes.
+    pub outflow: Option<RegionNodeIdx>,
+    /// Rain collected over the catchment of the lake.
+    pub inflow: T,
+    /// Water lost from the surface of the lake.
+    pub evaporation: T,
+}
+impl<T: RealField> Lake<T> {
+    /// Returns true for lakes without an outflow, which turn into salt lakes.
+    pub fn is_endorheic(&self) -> bool {
+        self.outflow.is_none()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lakes<T: RealField> {
+    pub lakes: Vec<Lake<T>>,
+    lake_of: Vec<Option<usize>>,
+    depths: Vec<T>,
+}
+impl<T: RealField> Lakes<T> {
+    /// ID of the lake covering a region, an index into `lakes`.
+    pub fn lake(&self, region: RegionNodeIdx) -> Option<usize> {
+        self.lake_of[region.index()]
+    }
+
+    /// Elevation of the water surface over a region, `None` if the region is dry.
+    pub fn surface(&self, region: RegionNodeIdx) -> Option<T> {
+        self.lake(region).map(|lake| self.lakes[lake].surface)
+    }
+
+    /// Depth of the water over a region, zero if the region is dry.
+    pub fn depth(&self, region: RegionNodeIdx) -> T {
+        self.depths[region.index()]
+    }
+
+    pub fn is_lake(&self, region: RegionNodeIdx) -> bool {
+        self.lake(region).is_some()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: T,
+    min_depth: T,
+    min_size: usize,
+    rain: T,
+    evaporation: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Depressions whose surface is at or below this elevation are sea floor, not lakes.
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Depressions are only kept if their deepest region is deeper than this.
+    pub fn with_min_depth(mut self, min_depth: T) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Depressions are only kept if they cover at least this many regions.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Water every region contributes to the lake it drains into.
+    pub fn with_rain(mut self, rain: T) -> Self {
+        self.rain = rain;
+        self
+    }
+
+    /// Water every lake region loses, a lake becomes endorheic when this exceeds its inflow.
+    pub fn with_evaporation(mut self, evaporation: T) -> Self {
+        self.evaporation = evaporation;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: 0.3.into(),
+            min_depth: 0.001.into(),
+            min_size: 1,
+            rain: 1.0.into(),
+            evaporation: 2.0.into(),
+        }
+    }
+}
+
+pub fn visit<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Lakes<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.min_depth < T::zero() {
+        return Err(failure::format_err!("Lake minimum depth must not be negative"));
+    }
+
+    let count = region_graph.node_count();
+    let elevations = region_graph
+        .node_indices()
+        .map(|idx| region_graph[idx].value.elevation())
+        .collect::<Vec<_>>();
+    // Filling flat leaves every basin at exactly the level of its spill point
+    let routing = flow::route(region_graph, &elevations, &flow::Settings::default().with_keep_depths(true));
+    let catchment = routing.accumulate(&vec![settings.rain; count]);
+
+    let mut lakes = Vec::new();
+    let mut lake_of = vec![None; count];
+    let mut depths = vec![T::zero(); count];
+    let mut visited = vec![false; count];
+    let mut basin = Vec::new();
+    let mut queue = VecDeque::new();
+
+    for start in region_graph.node_indices() {
+        if visited[start.index()] || !routing.is_filled(start) {
+            continue;
+        }
+
+        // Connected filled regions at the same level form one basin
+        let surface = routing.filled(start);
+        basin.clear();
+        visited[start.index()] = true;
+        queue.push_back(start);
+        while let Some(region) = queue.pop_front() {
+            basin.push(region);
+            for neighbor in region_graph.neighbors(region) {
+                if !visited[neighbor.index()] && routing.is_filled(neighbor) && (routing.filled(neighbor) - surface).abs() <= T::default_epsilon() {
+                    visited[neighbor.index()] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        if surface <= settings.sea_level {
+            continue;
+        }
+        let deepest = basin.iter().fold(T::zero(), |acc, region| acc.max(surface - elevations[region.index()]));
+        if basin.len() < settings.min_size || deepest <= settings.min_depth {
+            continue;
+        }
+
+        let id = lakes.len();
+        for region in &basin {
+            lake_of[region.index()] = Some(id);
+            depths[region.index()] = surface - elevations[region.index()];
+        }
+
+        // Water leaves the basin from the regions whose receiver lies outside it, carrying the whole catchment,
+        // and the lowest of those receivers is where the lake drains to
+        let mut inflow = T::zero();
+        let mut spill: Option<RegionNodeIdx> = None;
+        for region in &basin {
+            match routing.receiver(*region) {
+                Some(next) if lake_of[next.index()] == Some(id) => {}
+                receiver => {
+                    inflow += catchment[region.index()];
+                    if let Some(next) = receiver {
+                        spill = match spill {
+                            Some(lowest) if routing.filled(lowest) <= routing.filled(next) => Some(lowest),
+                            _ => Some(next),
+                        };
+                    }
+                }
+            }
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let evaporation = settings.evaporation * T::from(basin.len() as f32);
+        lakes.push(Lake {
+            regions: basin.clone(),
+            surface,
+            outflow: if evaporation > inflow { None } else { spill },
+            inflow,
+            evaporation,
+        });
+    }
+
+    Ok(Lakes { lakes, lake_of, depths })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate};
+    use nalgebra::Point2;
+    use rand::Rng;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+        /// Whether the region is under an endorheic lake and how deep, filled in for drawing.
+        lake: Option<(bool, f32)>,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn lakes_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        // Roughen the terrain so it has plenty of closed depressions
+        for region in region_graph.node_weights_mut() {
+            let elevation = region.value.elevation() + rng.gen_range(0.0, 0.05);
+            region.value.set_elevation(elevation);
+        }
+
+        let lakes = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+        let elevations = region_graph
+            .node_indices()
+            .map(|idx| region_graph[idx].value.elevation())
+            .collect::<Vec<_>>();
+        let routing = flow::route(&region_graph, &elevations, &flow::Settings::default().with_keep_depths(true));
+
+        assert!(!lakes.lakes.is_empty());
+        for (id, lake) in lakes.lakes.iter().enumerate() {
+            for region in &lake.regions {
+                assert_eq!(lakes.lake(*region), Some(id));
+                assert!(lakes.depth(*region) >= 0.0);
+                assert!((lakes.surface(*region).unwrap() - region_graph[*region].value.elevation() - lakes.depth(*region)).abs() < 1.0e-5);
+            }
+            assert_eq!(lake.is_endorheic(), lake.evaporation > lake.inflow);
+            if let Some(outflow) = lake.outflow {
+                assert!(!lake.regions.contains(&outflow));
+                // The lake drains where the flow routing leaves it
+                assert!(lake.regions.iter().any(|region| routing.receiver(*region) == Some(outflow)));
+            }
+        }
+        assert!(lakes.lakes.iter().any(|lake| !lake.is_endorheic()));
+
+        // Without evaporation every lake overflows
+        let wet = visit(&region_graph, &Settings::<f32>::default().with_evaporation(0.0)).unwrap();
+        assert!(wet.lakes.iter().all(|lake| lake.outflow.is_some()));
+
+        for lake in &lakes.lakes {
+            for region in &lake.regions {
+                region_graph[*region].value.lake = Some((lake.is_endorheic(), lakes.depth(*region)));
+            }
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| match region.value.lake {
+            Some((true, _)) => image::Rgb([200, 200, 255]),
+            Some((false, depth)) => image::Rgb([0, 0, (255.0 - 500.0 * depth).max(80.0) as u8]),
+            None => {
+                let shade = (200.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+                image::Rgb([shade, shade, shade])
+            }
+        });
+
+        imgbuf.save("output/lakes.png").unwrap();
+    }
+
+    #[test]
+    pub fn lakes_under_sea() {
+        let (mut region_graph, _, _) = peak_automata::tests::test_graph::<TestInner>();
+
+        // Sea floor in the west and land in the east, each with a single pit
+        let sea_pit = node_for_coordinate(&region_graph, Point2::new(200.0, 512.0)).unwrap();
+        let land_pit = node_for_coordinate(&region_graph, Point2::new(700.0, 512.0)).unwrap();
+        for region in region_graph.node_indices() {
+            let node = &mut region_graph[region];
+            node.value.elevation = if node.pos.x < 400.0 { 0.1 } else { 0.5 };
+        }
+        region_graph[sea_pit].value.elevation = 0.05;
+        region_graph[land_pit].value.elevation = 0.45;
+
+        let lakes = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+        assert_eq!(lakes.lakes.len(), 1);
+        assert!(lakes.is_lake(land_pit));
+        assert!(!lakes.is_lake(sea_pit));
+        assert!(lakes.depth(sea_pit).abs() < std::f32::EPSILON);
+
+        // Lowering the sea below the floor turns the pit into a lake
+        let drained = visit(&region_graph, &Settings::<f32>::default().with_sea_level(0.0)).unwrap();
+        assert!(drained.is_lake(sea_pit) && drained.is_lake(land_pit));
+    }
+}
@@ -0,0 +1,287 @@
+//! Landscape Evolution
+//! Time-stepped stream power model after the `FastScape` approach. Every step raises the terrain by an uplift field,
+//! incises rivers in proportion to drainage area and slope using an implicit solver along the flow routing network,
+//! and smooths hillslopes with linear diffusion. Regions on the edge of the graph are held at base level, so
+//! rivers cut back from the map boundary into the uplifted terrain.
+//!
+use crate::{
+    dual_graph::{is_boundary_region, RegionEdge, RegionNode, RegionNodeIdx},
+    flow, slope, HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+/// Newton iterations used to solve the incision step when the slope exponent is not one.
+const NEWTON_ITERATIONS: usize = 10;
+
+/// Largest explicit diffusion coefficient per sub-step that keeps hillslope diffusion stable.
+const DIFFUSION_LIMIT: f32 = 0.5;
+
+/// Most diffusion sub-steps allowed per step before the settings are rejected as too stiff.
+const MAX_DIFFUSION_SUBSTEPS: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct Evolution<T: RealField> {
+    /// Elevation of every region, indexed by region, recorded every `snapshot_interval` steps.
+    pub snapshots: Vec<Vec<T>>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    steps: usize,
+    timestep: T,
+    erodibility: T,
+    area_exponent: T,
+    slope_exponent: T,
+    diffusion: T,
+    snapshot_interval: usize,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    pub fn with_timestep(mut self, timestep: T) -> Self {
+        self.timestep = timestep;
+        self
+    }
+
+    /// Stream power coefficient `K` in `K * A^m * S^n`.
+    pub fn with_erodibility(mut self, erodibility: T) -> Self {
+        self.erodibility = erodibility;
+        self
+    }
+
+    /// Drainage area and slope exponents `m` and `n` in `K * A^m * S^n`.
+    pub fn with_exponents(mut self, area_exponent: T, slope_exponent: T) -> Self {
+        self.area_exponent = area_exponent;
+        self.slope_exponent = slope_exponent;
+        self
+    }
+
+    /// Hillslope diffusivity in squared graph units per unit of time.
+    pub fn with_diffusion(mut self, diffusion: T) -> Self {
+        self.diffusion = diffusion;
+        self
+    }
+
+    /// Record the elevations every this many steps, zero disables snapshots.
+    pub fn with_snapshot_interval(mut self, snapshot_interval: usize) -> Self {
+        self.snapshot_interval = snapshot_interval;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            steps: 100,
+            timestep: 1.0.into(),
+            erodibility: 0.02.into(),
+            area_exponent: 0.5.into(),
+            slope_exponent: 1.0.into(),
+            diffusion: 5.0.into(),
+            snapshot_interval: 0,
+        }
+    }
+}
+
+/// Builds an uplift map from the current elevations, for example the output of peak automata, scaled by `rate`.
+pub fn uplift_from_elevation<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, rate: T) -> Vec<T>
+where
+    T: RealField,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    region_graph
+        .node_indices()
+        .map(|idx| region_graph[idx].value.elevation().max(T::zero()) * rate)
+        .collect()
+}
+
+/// Explicit hillslope diffusion, prepared once since the weights and the stable sub-step only depend on the geometry.
+struct Diffusion<T: RealField> {
+    weights: Vec<Vec<(RegionNodeIdx, T)>>,
+    substeps: usize,
+    coefficient: T,
+}
+impl<T: RealField + From<f32>> Diffusion<T> {
+    fn new<V, E: EdgeType>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Self, failure::Error> {
+        let weights = slope::laplacian_weights(region_graph);
+
+        let largest = weights
+            .iter()
+            .map(|weights| weights.iter().fold(T::zero(), |acc, (_, weight)| acc + *weight))
+            .fold(T::zero(), T::max)
+            * settings.diffusion
+            * settings.timestep;
+        let substeps = nalgebra::try_convert::<T, f64>((largest / T::from(DIFFUSION_LIMIT)).ceil())
+            .unwrap_or(1.0)
+            .max(1.0);
+        // Regions packed very closely together would otherwise stall every step
+        #[allow(clippy::cast_precision_loss)]
+        let limit = MAX_DIFFUSION_SUBSTEPS as f64;
+        if substeps.is_nan() || substeps > limit {
+            return Err(failure::format_err!(
+                "Hillslope diffusion needs more than {} sub-steps per step, lower the diffusion or the timestep",
+                MAX_DIFFUSION_SUBSTEPS
+            ));
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let coefficient = settings.diffusion * settings.timestep / T::from(substeps as f32);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let substeps = substeps as usize;
+        Ok(Self {
+            weights,
+            substeps,
+            coefficient,
+        })
+    }
+
+    fn apply(&self, base_level: &[bool], elevations: &mut [T]) {
+        let mut next = elevations.to_vec();
+        for _ in 0..self.substeps {
+            for (i, weights) in self.weights.iter().enumerate() {
+                if base_level[i] {
+                    continue;
+                }
+                let laplacian = weights.iter().fold(T::zero(), |acc, (neighbor, weight)| {
+                    acc + (elevations[neighbor.index()] - elevations[i]) * *weight
+                });
+                next[i] = elevations[i] + self.coefficient * laplacian;
+            }
+            elevations.copy_from_slice(&next);
+        }
+    }
+}
+
+pub fn visit<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    uplift: &[T],
+) -> Result<Evolution<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    E: EdgeType,
+{
+    let count = region_graph.node_count();
+    if uplift.len() != count {
+        return Err(failure::format_err!("Uplift map has {} entries for {} regions", uplift.len(), count));
+    }
+
+    let base_level = region_graph
+        .node_indices()
+        .map(|idx| is_boundary_region(region_graph, idx))
+        .collect::<Vec<_>>();
+    let diffusion = Diffusion::new(region_graph, settings)?;
+    let mut elevations = region_graph.node_weights_mut().map(|region| region.value.elevation()).collect::<Vec<_>>();
+    let mut snapshots = Vec::new();
+
+    for step in 1..=settings.steps {
+        for (i, elevation) in elevations.iter_mut().enumerate() {
+            if !base_level[i] {
+                *elevation += uplift[i] * settings.timestep;
+            }
+        }
+
+        // Route over the depression filled surface, so water in pits still reaches base level through the lake
+        let routing = flow::route(region_graph, &elevations, &flow::Settings::default());
+
+        // Implicit incision from the outlets up, so every receiver is already at its new elevation
+        for region in routing.upstream_order().rev() {
+            let i = region.index();
+            let next = match routing.receiver(region) {
+                Some(next) if !base_level[i] && elevations[i] > elevations[next.index()] => next,
+                _ => continue,
+            };
+
+            let length = T::from(nalgebra::distance(&region_graph[region].pos, &region_graph[next].pos));
+            let factor = settings.erodibility * settings.timestep * routing.accumulation(region).powf(settings.area_exponent)
+                / length.powf(settings.slope_exponent);
+            let outlet = elevations[next.index()];
+
+            elevations[i] = if settings.slope_exponent == T::one() {
+                (elevations[i] + factor * outlet) / (T::one() + factor)
+            } else {
+                let start = elevations[i];
+                let mut current = start;
+                for _ in 0..NEWTON_ITERATIONS {
+                    let drop = current - outlet;
+                    if drop <= T::zero() {
+                        break;
+                    }
+                    let residual = current - start + factor * drop.powf(settings.slope_exponent);
+                    let derivative = T::one() + settings.slope_exponent * factor * drop.powf(settings.slope_exponent - T::one());
+                    current -= residual / derivative;
+                }
+                current.max(outlet)
+            };
+        }
+
+        diffusion.apply(&base_level, &mut elevations);
+
+        if settings.snapshot_interval > 0 && step % settings.snapshot_interval == 0 {
+            snapshots.push(elevations.clone());
+        }
+    }
+
+    for (region, elevation) in region_graph.node_weights_mut().zip(elevations) {
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(Evolution { snapshots })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn landscape_evolution_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        // Grow the landscape from a flat plain using the peak automata output as the uplift pattern
+        let uplift = uplift_from_elevation(&region_graph, 0.01);
+        for region in region_graph.node_weights_mut() {
+            region.value.set_elevation(0.0);
+        }
+
+        let settings = Settings::<f32>::default().with_steps(50).with_snapshot_interval(10);
+        let evolution = visit(&mut region_graph, &settings, &uplift).unwrap();
+
+        assert_eq!(evolution.snapshots.len(), 5);
+        for idx in region_graph.node_indices() {
+            let elevation = region_graph[idx].value.elevation();
+            assert!(elevation.is_finite() && elevation >= 0.0);
+            if is_boundary_region(&region_graph, idx) {
+                assert!(elevation.abs() < std::f32::EPSILON);
+            }
+        }
+        // Diffusion too stiff for a bounded number of sub-steps is rejected before anything changes
+        assert!(visit(&mut region_graph, &Settings::<f32>::default().with_diffusion(1.0e9), &uplift).is_err());
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/landscape_evolution.png").unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! Latitude
+//! Maps region positions to latitude in degrees. A flat map stretches a range of latitudes over its y axis, north at
+//! the top by default, while graphs whose positions already are longitude and latitude in degrees are read directly.
+//! Climate stages use the latitude for insolation and circulation, and the north direction to orient winds.
+//!
+use crate::dual_graph::{RegionEdge, RegionNode, RegionNodeIdx};
+use nalgebra::{RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Latitude<T: RealField> {
+    /// Latitudes at the top and bottom of the map, linearly interpolated over the y axis of the region positions.
+    Planar { top: T, bottom: T },
+    /// Region positions are longitude and latitude in degrees.
+    Degrees,
+}
+impl<T: RealField + From<f32>> Latitude<T> {
+    /// Whole globe from the north pole at the top of the map to the south pole at the bottom.
+    pub fn default() -> Self {
+        Latitude::Planar {
+            top: 90.0.into(),
+            bottom: (-90.0).into(),
+        }
+    }
+
+    pub fn visit<V, E: EdgeType>(&self, region_graph: &Graph<RegionNode<V>, RegionEdge, E>) -> Latitudes<T> {
+        match *self {
+            Latitude::Planar { top, bottom } => {
+                let (low, high) = region_graph.raw_nodes().iter().fold((std::f32::MAX, std::f32::MIN), |(low, high), node| {
+                    (low.min(node.weight.pos.y), high.max(node.weight.pos.y))
+                });
+                let extent = T::from((high - low).max(std::f32::EPSILON));
+
+                Latitudes {
+                    latitudes: region_graph
+                        .raw_nodes()
+                        .iter()
+                        .map(|node| top + (bottom - top) * T::from(node.weight.pos.y - low) / extent)
+                        .collect(),
+                    north: if top >= bottom {
+                        Vector2::new(T::zero(), -T::one())
+                    } else {
+                        Vector2::new(T::zero(), T::one())
+                    },
+                }
+            }
+            Latitude::Degrees => Latitudes {
+                latitudes: region_graph.raw_nodes().iter().map(|node| T::from(node.weight.pos.y)).collect(),
+                north: Vector2::new(T::zero(), T::one()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Latitudes<T: RealField> {
+    latitudes: Vec<T>,
+    north: Vector2<T>,
+}
+impl<T: RealField> Latitudes<T> {
+    /// Latitude in degrees, positive in the northern hemisphere.
+    pub fn latitude(&self, region: RegionNodeIdx) -> T {
+        self.latitudes[region.index()]
+    }
+
+    /// Unit vector pointing north in graph coordinates.
+    pub fn north(&self) -> Vector2<T> {
+        self.north
+    }
+
+    /// Unit vector pointing east in graph coordinates, always along positive x.
+    pub fn east(&self) -> Vector2<T> {
+        Vector2::new(T::one(), T::zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate};
+    use nalgebra::Point2;
+
+    #[test]
+    fn latitude_mapping() {
+        let (region_graph, _, _) = peak_automata::tests::test_graph::<()>();
+
+        let globe = Latitude::<f32>::default().visit(&region_graph);
+        let top = node_for_coordinate(&region_graph, Point2::new(512.0, 0.0)).unwrap();
+        let middle = node_for_coordinate(&region_graph, Point2::new(512.0, 512.0)).unwrap();
+        let bottom = node_for_coordinate(&region_graph, Point2::new(512.0, 1024.0)).unwrap();
+        assert!(globe.latitude(top) > 85.0 && globe.latitude(bottom) < -85.0 && globe.latitude(middle).abs() < 5.0);
+        assert_eq!(globe.north(), Vector2::new(0.0, -1.0));
+        assert_eq!(globe.east(), Vector2::new(1.0, 0.0));
+
+        // A southern hemisphere map has its pole at the bottom
+        let south = Latitude::Planar { top: -10.0, bottom: -60.0 }.visit(&region_graph);
+        for region in region_graph.node_indices() {
+            let latitude = south.latitude(region);
+            assert!((-60.0..=-10.0).contains(&latitude));
+            let expected = -10.0 - 50.0 * region_graph[region].pos.y / 1024.0;
+            assert!((latitude - expected).abs() < 1.0);
+        }
+
+        let degrees = Latitude::<f32>::Degrees.visit(&region_graph);
+        assert!((degrees.latitude(middle) - region_graph[middle].pos.y).abs() < std::f32::EPSILON);
+        assert_eq!(degrees.north(), Vector2::new(0.0, 1.0));
+        assert_eq!(degrees.east(), Vector2::new(1.0, 0.0));
+    }
+}
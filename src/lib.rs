@@ -2,9 +2,35 @@
 #![allow(dead_code, clippy::module_name_repetitions)]
 use nalgebra::{RealField, Vector2};
 
+pub mod biome;
+pub mod brush;
+pub mod cryosphere;
+pub mod currents;
+pub mod distance;
 pub mod dual_graph;
+pub mod flow;
+pub mod heightmap;
+pub mod hydraulic_erosion;
+pub mod koppen;
+pub mod lakes;
+pub mod landscape_evolution;
+pub mod latitude;
+pub mod moisture;
+pub mod noise;
 pub mod peak_automata;
+pub mod raster;
+pub mod redistribute;
+pub mod rivers;
+mod scored;
+pub mod seasons;
 pub mod simple_wind;
+pub mod slope;
+pub mod spatial;
+pub mod tectonics;
+pub mod temperature;
+pub mod thermal_erosion;
+pub mod water;
+pub mod wind_map;
 
 pub trait HasValue {
     type Value;
@@ -13,6 +39,23 @@ pub trait HasValue {
     fn value_mut(&mut self) -> &mut Self::Value;
 }
 
+/// How a stage combines the value it computed with the value already stored on a region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Blend {
+    Replace,
+    Add,
+    Multiply,
+}
+impl Blend {
+    pub fn apply<T: RealField>(self, current: T, value: T) -> T {
+        match self {
+            Blend::Replace => value,
+            Blend::Add => current + value,
+            Blend::Multiply => current * value,
+        }
+    }
+}
+
 pub trait HasElevation<T: RealField> {
     fn elevation(&self) -> T;
     fn set_elevation(&mut self, height: T);
@@ -33,8 +76,14 @@ pub trait HasWind<T: RealField> {
     fn set_wind_vector(&mut self, vector: Vector2<T>);
 }
 
+/// Precipitation in millimeters per year.
 pub trait AnnualRainfall<T: RealField> {
-    fn annual_rainfaill(&self) -> T {
-        unimplemented!()
-    }
+    fn annual_rainfall(&self) -> T;
+    fn set_annual_rainfall(&mut self, rainfall: T);
+}
+
+/// Biome of a region, `biome::Biome` unless a custom biome table is used.
+pub trait HasBiome<B> {
+    fn biome(&self) -> B;
+    fn set_biome(&mut self, biome: B);
 }
@@ -0,0 +1,366 @@
+//! Moisture
+//! Carries water vapour along the wind and rains it out. Air over the ocean and lakes picks up moisture up to a
+//! humidity, and every region takes the air arriving from its upwind neighbors. How much vapour air can hold grows with
+//! the region temperature, so air cooling as it climbs loses the excess, and air forced uphill rains out faster while
+//! descending air dries. Mountain ranges therefore get wet windward slopes and rain shadows behind them. Rainfall is
+//! written through `AnnualRainfall` and the relative humidity through `HasMoisture`.
+//!
+use crate::{
+    currents::Currents,
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    simple_wind,
+    water::{self, Surface, Water},
+    AnnualRainfall, HasElevation, HasMoisture, HasTemperature, HasWind,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+/// Growth of the vapour capacity of air per degree, from the Clausius-Clapeyron relation.
+const CAPACITY_GROWTH: f32 = 0.07;
+
+#[derive(Clone, Debug)]
+pub struct Precipitation<T: RealField> {
+    rainfall: Vec<T>,
+    humidity: Vec<T>,
+}
+impl<T: RealField> Precipitation<T> {
+    /// Rainfall in millimeters per year.
+    pub fn rainfall(&self, region: RegionNodeIdx) -> T {
+        self.rainfall[region.index()]
+    }
+
+    /// Relative humidity of the air leaving the region, within `[0, 1]`.
+    pub fn humidity(&self, region: RegionNodeIdx) -> T {
+        self.humidity[region.index()]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: T,
+    height_scale: T,
+    capacity: T,
+    evaporation: T,
+    inflow: T,
+    rain_distance: T,
+    orographic: T,
+    lee: T,
+    recycling: T,
+    rainfall_scale: T,
+    current_response: T,
+    steps: usize,
+    warm_start: bool,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Meters of height per unit of elevation above sea level.
+    pub fn with_height_scale(mut self, height_scale: T) -> Self {
+        self.height_scale = height_scale;
+        self
+    }
+
+    /// Vapour air can hold at zero degrees, in the units rainfall is scaled from.
+    pub fn with_capacity(mut self, capacity: T) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Relative humidity air over water is brought up to.
+    pub fn with_evaporation(mut self, evaporation: T) -> Self {
+        self.evaporation = evaporation;
+        self
+    }
+
+    /// Relative humidity of air blowing onto the map over land.
+    pub fn with_inflow(mut self, inflow: T) -> Self {
+        self.inflow = inflow;
+        self
+    }
+
+    /// Distance in graph units over which air on flat ground rains out about two thirds of its vapour.
+    pub fn with_rain_distance(mut self, rain_distance: T) -> Self {
+        self.rain_distance = rain_distance;
+        self
+    }
+
+    /// Additional fraction rained out per kilometer of climb, and how strongly each kilometer of descent suppresses
+    /// rain.
+    pub fn with_orographic(mut self, orographic: T, lee: T) -> Self {
+        self.orographic = orographic;
+        self.lee = lee;
+        self
+    }
+
+    /// Fraction of the rain over land that evaporates again and stays in the air.
+    pub fn with_recycling(mut self, recycling: T) -> Self {
+        self.recycling = recycling;
+        self
+    }
+
+    /// Millimeters per year for each unit of vapour rained out.
+    pub fn with_rainfall_scale(mut self, rainfall_scale: T) -> Self {
+        self.rainfall_scale = rainfall_scale;
+        self
+    }
+
+    /// Change of the ocean evaporation per degree of sea surface anomaly, used by `visit_with_currents`. Warm currents
+    /// moisten the air above them and cold currents dry it.
+    pub fn with_current_response(mut self, current_response: T) -> Self {
+        self.current_response = current_response;
+        self
+    }
+
+    /// Largest number of transport steps, stops early once the vapour settles.
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Continue from the humidity already on the regions instead of dry air, so repeated calls advance the same air.
+    pub fn with_warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
+    pub fn sea_level(&self) -> T {
+        self.sea_level
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: 0.3.into(),
+            height_scale: 8000.0.into(),
+            capacity: 1.0.into(),
+            evaporation: 0.8.into(),
+            inflow: 0.5.into(),
+            rain_distance: 300.0.into(),
+            orographic: 0.5.into(),
+            lee: 2.0.into(),
+            recycling: 0.3.into(),
+            rainfall_scale: 8000.0.into(),
+            current_response: 0.03.into(),
+            steps: 500,
+            warm_start: false,
+        }
+    }
+}
+
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Precipitation<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T> + HasMoisture<T> + AnnualRainfall<T>,
+    E: EdgeType,
+{
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    visit_with_water(region_graph, settings, &water, None)
+}
+
+/// Like `visit`, with evaporation over the ocean raised above warm currents and lowered above cold ones.
+pub fn visit_with_currents<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    currents: &Currents<T>,
+) -> Result<Precipitation<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T> + HasMoisture<T> + AnnualRainfall<T>,
+    E: EdgeType,
+{
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    visit_with_water(region_graph, settings, &water, Some(currents))
+}
+
+/// Like `visit_with_currents` when given currents and `visit` otherwise, with the water at the settings sea level
+/// already computed, for callers that run the stage repeatedly over the same terrain.
+pub fn visit_with_water<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    water: &Water,
+    currents: Option<&Currents<T>>,
+) -> Result<Precipitation<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T> + HasMoisture<T> + AnnualRainfall<T>,
+    E: EdgeType,
+{
+    let response = |region| currents.map_or(T::one(), |currents| T::one() + settings.current_response * currents.anomaly(region));
+    visit_with_evaporation(region_graph, settings, water, |region| {
+        (settings.evaporation * response(region)).max(T::zero()).min(T::one())
+    })
+}
+
+fn visit_with_evaporation<T, V, E, F>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    water: &Water,
+    evaporation: F,
+) -> Result<Precipitation<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T> + HasMoisture<T> + AnnualRainfall<T>,
+    E: EdgeType,
+    F: Fn(RegionNodeIdx) -> T,
+{
+    let unit = |value: T| value >= T::zero() && value <= T::one();
+    if !unit(settings.evaporation) || !unit(settings.inflow) || !unit(settings.recycling) {
+        return Err(failure::format_err!("Moisture humidities and recycling must be within [0, 1]"));
+    }
+    if settings.rain_distance <= T::zero() {
+        return Err(failure::format_err!("Rain distance must be positive"));
+    }
+
+    let count = region_graph.node_count();
+    let wet = region_graph
+        .node_indices()
+        .map(|region| water.surface(region) != Surface::Land)
+        .collect::<Vec<_>>();
+    let capacities = region_graph
+        .node_indices()
+        .map(|region| settings.capacity * (T::from(CAPACITY_GROWTH) * region_graph[region].value.temperature()).exp())
+        .collect::<Vec<_>>();
+    let surfaces = region_graph
+        .node_indices()
+        .map(|region| region_graph[region].value.elevation().max(settings.sea_level))
+        .collect::<Vec<_>>();
+    let upwind = simple_wind::upwind_of_regions(region_graph);
+
+    let mut vapour = if settings.warm_start {
+        region_graph
+            .node_indices()
+            .map(|region| region_graph[region].value.moisture().max(T::zero()).min(T::one()) * capacities[region.index()])
+            .collect::<Vec<_>>()
+    } else {
+        vec![T::zero(); count]
+    };
+    let mut next = vapour.clone();
+    let mut rain = vec![T::zero(); count];
+    let settled = settings.capacity * T::from(1.0e-5);
+
+    for _ in 0..settings.steps {
+        let mut change = T::zero();
+        for region in 0..count {
+            let capacity = capacities[region];
+            let evaporation = evaporation(RegionNodeIdx::new(region));
+            let position = region_graph[RegionNodeIdx::new(region)].pos;
+            let (arriving, height, length, total) = upwind[region].iter().fold(
+                (T::zero(), T::zero(), T::zero(), T::zero()),
+                |(arriving, height, length, total), (neighbor, weight)| {
+                    let distance = T::from(nalgebra::distance(&position, &region_graph[*neighbor].pos));
+                    (
+                        arriving + vapour[neighbor.index()] * *weight,
+                        height + surfaces[neighbor.index()] * *weight,
+                        length + distance * *weight,
+                        total + *weight,
+                    )
+                },
+            );
+
+            // Air blowing in from outside the map or standing still is taken from the surface below
+            let (mut air, climb, background) = if total > T::zero() {
+                let climb = (surfaces[region] - height / total) * settings.height_scale / T::from(1000.0);
+                let background = T::one() - (-length / total / settings.rain_distance).exp();
+                (arriving / total, climb, background)
+            } else if wet[region] {
+                (capacity * evaporation, T::zero(), T::zero())
+            } else {
+                (capacity * settings.inflow, T::zero(), T::zero())
+            };
+            if wet[region] {
+                air = air.max(capacity * evaporation);
+            }
+
+            let saturation = (air - capacity).max(T::zero());
+            air -= saturation;
+            let rate = if climb > T::zero() {
+                background + settings.orographic * climb
+            } else {
+                background / (T::one() - settings.lee * climb)
+            };
+            let shower = air * rate.min(T::one());
+            let precipitation = saturation + shower;
+
+            air -= shower;
+            if !wet[region] {
+                air += precipitation * settings.recycling;
+            }
+
+            change = change.max((air - vapour[region]).abs());
+            next[region] = air;
+            rain[region] = precipitation;
+        }
+
+        std::mem::swap(&mut vapour, &mut next);
+        if change < settled {
+            break;
+        }
+    }
+
+    let mut precipitation = Precipitation {
+        rainfall: Vec::with_capacity(count),
+        humidity: Vec::with_capacity(count),
+    };
+    for (index, region) in region_graph.node_weights_mut().enumerate() {
+        let rainfall = rain[index] * settings.rainfall_scale;
+        let humidity = (vapour[index] / capacities[index]).min(T::one());
+
+        precipitation.rainfall.push(rainfall);
+        precipitation.humidity.push(humidity);
+        region.value.set_annual_rainfall(rainfall);
+        region.value.set_moisture(humidity);
+    }
+
+    Ok(precipitation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate, tests::ClimateInner};
+    use crate::{simple_wind, temperature};
+    use nalgebra::Point2;
+
+    type TestInner = ClimateInner;
+
+    #[test]
+    pub fn moisture_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::test_graph::<TestInner>();
+
+        // Ocean in the east, the wind blows west over a coastal plain into a north south mountain range
+        for region in region_graph.node_weights_mut() {
+            let ridge = (-(region.pos.x - 500.0).powi(2) / (2.0 * 40.0 * 40.0)).exp();
+            region.value.elevation = if region.pos.x > 800.0 { 0.1 } else { 0.35 + 0.4 * ridge };
+        }
+        let at = |x: f32| node_for_coordinate(&region_graph, Point2::new(x, 512.0)).unwrap();
+        let (coast, windward, leeward, interior) = (at(750.0), at(560.0), at(400.0), at(100.0));
+
+        simple_wind::visit(&mut region_graph, &simple_wind::Settings::<f32>::default(), &mut rng).unwrap();
+        temperature::visit(&mut region_graph, &temperature::Settings::<f32>::default()).unwrap();
+        let precipitation = visit(&mut region_graph, &Settings::<f32>::default()).unwrap();
+
+        let rainfall = |region| precipitation.rainfall(region);
+        assert!(rainfall(windward) > 2.0 * rainfall(leeward));
+        assert!(rainfall(coast) > rainfall(interior));
+        assert!(precipitation.humidity(windward) > precipitation.humidity(leeward));
+        for region in region_graph.node_indices() {
+            let value = &region_graph[region].value;
+            assert!((value.annual_rainfall() - rainfall(region)).abs() < std::f32::EPSILON);
+            assert!((0.0..=1.0).contains(&value.moisture()));
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let wet = (region.value.annual_rainfall() / 3000.0).min(1.0).max(0.0);
+            if region.value.elevation() < 0.3 {
+                image::Rgb([0, 0, 128])
+            } else {
+                image::Rgb([(200.0 * (1.0 - wet)) as u8, (120.0 + 100.0 * wet) as u8, (60.0 + 120.0 * wet) as u8])
+            }
+        });
+
+        imgbuf.save("output/moisture.png").unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+//! Noise
+//! Visits the graph, sampling fractal gradient noise at every region position and writing the result as elevation.
+//! Octaves are layered either as fBm or as a ridged multifractal, and the sample position can be domain warped by a
+//! second noise lookup. The result is blended with the existing elevation, so noise can modulate peak automata output.
+//!
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode},
+    Blend, HasElevation,
+};
+use nalgebra::{Point2, RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+const GRADIENTS: [(f64, f64); 8] = [
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+    (std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2),
+    (-std::f64::consts::FRAC_1_SQRT_2, std::f64::consts::FRAC_1_SQRT_2),
+    (std::f64::consts::FRAC_1_SQRT_2, -std::f64::consts::FRAC_1_SQRT_2),
+    (-std::f64::consts::FRAC_1_SQRT_2, -std::f64::consts::FRAC_1_SQRT_2),
+];
+
+/// Offsets applied to the two warp lookups so they are decorrelated from the base lookup.
+const WARP_OFFSETS: [(f32, f32); 2] = [(5.2, 1.3), (1.7, 9.2)];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Fractal {
+    /// Fractional brownian motion, a plain sum of octaves.
+    Fbm,
+    /// Ridged multifractal, octaves are folded around zero and weighted by the previous octave.
+    Ridged,
+}
+
+/// Seeded 2D gradient noise lattice.
+#[derive(Clone, Debug)]
+pub struct Noise {
+    permutation: Vec<usize>,
+}
+impl Noise {
+    pub fn new<R>(rng: &mut R) -> Self
+    where
+        R: rand::Rng + ?Sized,
+    {
+        use rand::seq::SliceRandom;
+
+        let mut permutation = (0..256).collect::<Vec<_>>();
+        permutation.shuffle(rng);
+
+        Self {
+            permutation: permutation.iter().chain(permutation.iter()).copied().collect(),
+        }
+    }
+
+    fn hash(&self, x: i64, y: i64) -> usize {
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        let (x, y) = ((x & 255) as usize, (y & 255) as usize);
+        self.permutation[self.permutation[x] + y]
+    }
+
+    fn gradient(&self, ix: i64, iy: i64, dx: f64, dy: f64) -> f64 {
+        let (gx, gy) = GRADIENTS[self.hash(ix, iy) % GRADIENTS.len()];
+        gx * dx + gy * dy
+    }
+
+    /// Samples the lattice at the given coordinate, the result lies roughly within `[-1, 1]`.
+    #[allow(clippy::cast_possible_truncation, clippy::similar_names)]
+    pub fn sample<T: RealField>(&self, x: T, y: T) -> T {
+        let x = nalgebra::try_convert::<T, f64>(x).unwrap_or(0.0);
+        let y = nalgebra::try_convert::<T, f64>(y).unwrap_or(0.0);
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (ix, iy) = (x0 as i64, y0 as i64);
+
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+        let n00 = self.gradient(ix, iy, dx, dy);
+        let n10 = self.gradient(ix + 1, iy, dx - 1.0, dy);
+        let n01 = self.gradient(ix, iy + 1, dx, dy - 1.0);
+        let n11 = self.gradient(ix + 1, iy + 1, dx - 1.0, dy - 1.0);
+
+        let (u, v) = (fade(dx), fade(dy));
+        let value = lerp(lerp(n00, n10, u), lerp(n01, n11, u), v) * std::f64::consts::SQRT_2;
+
+        nalgebra::convert(value.max(-1.0).min(1.0))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    fractal: Fractal,
+    octaves: u32,
+    frequency: T,
+    lacunarity: T,
+    gain: T,
+    amplitude: T,
+    offset: T,
+    warp: T,
+    warp_frequency: T,
+    blend: Blend,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_fractal(mut self, fractal: Fractal) -> Self {
+        self.fractal = fractal;
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: T) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: T) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: T) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn with_amplitude(mut self, amplitude: T) -> Self {
+        self.amplitude = amplitude;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: T) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Displacement of the sample position in graph units, zero disables domain warping.
+    pub fn with_warp(mut self, warp: T, warp_frequency: T) -> Self {
+        self.warp = warp;
+        self.warp_frequency = warp_frequency;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: Blend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            fractal: Fractal::Fbm,
+            octaves: 6,
+            frequency: (1.0 / 256.0).into(),
+            lacunarity: 2.0.into(),
+            gain: 0.5.into(),
+            amplitude: 1.0.into(),
+            offset: 0.0.into(),
+            warp: 0.0.into(),
+            warp_frequency: (1.0 / 512.0).into(),
+            blend: Blend::Replace,
+        }
+    }
+}
+
+fn fbm<T: RealField>(noise: &Noise, point: Point2<T>, octaves: u32, lacunarity: T, gain: T) -> T {
+    let mut sum = T::zero();
+    let mut total = T::zero();
+    let mut amplitude = T::one();
+    let mut point = point;
+
+    for _ in 0..octaves {
+        sum += noise.sample(point.x, point.y) * amplitude;
+        total += amplitude;
+        amplitude *= gain;
+        point *= lacunarity;
+    }
+
+    sum / total
+}
+
+fn ridged<T: RealField>(noise: &Noise, point: Point2<T>, octaves: u32, lacunarity: T, gain: T) -> T {
+    let mut sum = T::zero();
+    let mut total = T::zero();
+    let mut amplitude = T::one();
+    let mut weight = T::one();
+    let mut point = point;
+
+    for _ in 0..octaves {
+        let ridge = T::one() - noise.sample(point.x, point.y).abs();
+        let signal = ridge * ridge * weight;
+        weight = signal.max(T::zero()).min(T::one());
+
+        sum += signal * amplitude;
+        total += amplitude;
+        amplitude *= gain;
+        point *= lacunarity;
+    }
+
+    sum / total
+}
+
+/// Evaluates the configured fractal at a position, returning `offset + amplitude * value` with `value` in `[0, 1]`.
+pub fn sample<T>(noise: &Noise, settings: &Settings<T>, position: Point2<T>) -> T
+where
+    T: RealField + From<f32>,
+{
+    let half: T = 0.5.into();
+
+    let position = if settings.warp == T::zero() {
+        position
+    } else {
+        let warp_at = |(x, y): (f32, f32)| {
+            let offset = Vector2::new(T::from(x), T::from(y));
+            fbm(
+                noise,
+                position * settings.warp_frequency + offset,
+                settings.octaves,
+                settings.lacunarity,
+                settings.gain,
+            )
+        };
+        position + Vector2::new(warp_at(WARP_OFFSETS[0]), warp_at(WARP_OFFSETS[1])) * settings.warp
+    };
+
+    let scaled = position * settings.frequency;
+    let value = match settings.fractal {
+        Fractal::Fbm => (fbm(noise, scaled, settings.octaves, settings.lacunarity, settings.gain) + T::one()) * half,
+        Fractal::Ridged => ridged(noise, scaled, settings.octaves, settings.lacunarity, settings.gain),
+    };
+
+    settings.offset + settings.amplitude * value.max(T::zero()).min(T::one())
+}
+
+pub fn visit<T, V, R, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>, rng: &mut R) -> Result<(), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    R: rand::Rng + ?Sized,
+    E: EdgeType,
+{
+    let noise = Noise::new(rng);
+
+    visit_with(region_graph, settings, &noise)
+}
+
+/// Same as `visit`, but samples an existing lattice so several passes can share one seed.
+pub fn visit_with<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    noise: &Noise,
+) -> Result<(), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.octaves == 0 {
+        return Err(failure::format_err!("Noise requires at least one octave"));
+    }
+
+    for region in region_graph.node_weights_mut() {
+        let position = Point2::new(T::from(region.pos.x), T::from(region.pos.y));
+        let value = sample(noise, settings, position);
+        let elevation = settings.blend.apply(region.value.elevation(), value);
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+    use petgraph::visit::IntoNodeReferences;
+    use rand::SeedableRng;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    fn noise_deterministic() {
+        let seed = [122, 154, 21, 182, 159, 131, 187, 243, 134, 230, 110, 10, 31, 174, 6, 4];
+        let a = Noise::new(&mut rand_xorshift::XorShiftRng::from_seed(seed));
+        let b = Noise::new(&mut rand_xorshift::XorShiftRng::from_seed(seed));
+
+        for i in 0..100 {
+            let (x, y) = (i as f32 * 0.37, i as f32 * 0.71);
+            let value = a.sample(x, y);
+            assert!((-1.0..=1.0).contains(&value));
+            assert!((value - b.sample(x, y)).abs() < std::f32::EPSILON);
+        }
+        assert!(a.sample(3.0_f32, 7.0).abs() < std::f32::EPSILON);
+    }
+
+    #[test]
+    pub fn noise_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let before = region_graph.node_references().map(|(_, n)| n.value.elevation()).collect::<Vec<_>>();
+
+        let settings = Settings::<f32>::default()
+            .with_fractal(Fractal::Ridged)
+            .with_warp(80.0, 1.0 / 512.0)
+            .with_blend(Blend::Multiply);
+        visit(&mut region_graph, &settings, &mut rng).unwrap();
+
+        for (region, before) in region_graph.node_references().map(|(_, n)| n).zip(before) {
+            assert!(region.value.elevation().abs() <= before.abs());
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/noise.png").unwrap();
+    }
+}
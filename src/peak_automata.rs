@@ -172,18 +172,21 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::dual_graph::{gen_dual_graph, BorderGraph};
+    use crate::dual_graph::{gen_dual_graph, BorderGraph, RegionGraph};
+    use crate::{water, AnnualRainfall, HasMoisture, HasTemperature, HasWind};
     use imageproc::drawing::Point as ImgPoint;
     use nalgebra::Vector2;
     use petgraph::visit::IntoNodeReferences;
     use rand::Rng;
     use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     #[derive(Default)]
     struct TestInner {
         elevation: f32,
+        ocean: bool,
     }
     impl HasElevation<f32> for TestInner {
         fn elevation(&self) -> f32 {
@@ -194,53 +197,143 @@ mod tests {
         }
     }
 
+    /// Region value shared by the climate stage tests, `data` holds whatever the stage under test stores on the region.
+    pub(crate) struct ClimateInner<D = ()> {
+        pub(crate) elevation: f32,
+        pub(crate) temperature: f32,
+        pub(crate) wind: Vector2<f32>,
+        pub(crate) moisture: f32,
+        pub(crate) rainfall: f32,
+        pub(crate) data: D,
+    }
+    impl<D: Default> Default for ClimateInner<D> {
+        fn default() -> Self {
+            Self {
+                elevation: 0.0,
+                temperature: 0.0,
+                wind: Vector2::zeros(),
+                moisture: 0.0,
+                rainfall: 0.0,
+                data: D::default(),
+            }
+        }
+    }
+    impl<D> HasElevation<f32> for ClimateInner<D> {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+    impl<D> HasTemperature<f32> for ClimateInner<D> {
+        fn temperature(&self) -> f32 {
+            self.temperature
+        }
+        fn set_temperature(&mut self, temperature: f32) {
+            self.temperature = temperature;
+        }
+    }
+    impl<D> HasWind<f32> for ClimateInner<D> {
+        fn wind_vector(&self) -> Vector2<f32> {
+            self.wind
+        }
+        fn set_wind_vector(&mut self, wind: Vector2<f32>) {
+            self.wind = wind;
+        }
+    }
+    impl<D> HasMoisture<f32> for ClimateInner<D> {
+        fn moisture(&self) -> f32 {
+            self.moisture
+        }
+        fn set_moisture(&mut self, moisture: f32) {
+            self.moisture = moisture;
+        }
+    }
+    impl<D> AnnualRainfall<f32> for ClimateInner<D> {
+        fn annual_rainfall(&self) -> f32 {
+            self.rainfall
+        }
+        fn set_annual_rainfall(&mut self, rainfall: f32) {
+            self.rainfall = rainfall;
+        }
+    }
+
     #[test]
     pub fn island_visitor() {
-        let dims = Vector2::new(1024.0, 1024.0);
-        let mut imgbuf = image::ImageBuffer::from_pixel(dims.x as u32, dims.y as u32, image::Rgb([222, 222, 222]));
+        let mut imgbuf = canvas();
+        let (mut region_graph, border_graph, _) = island::<TestInner>();
 
-        let mut rng = rand_xorshift::XorShiftRng::from_seed([122, 154, 21, 182, 159, 131, 187, 243, 134, 230, 110, 10, 31, 174, 6, 4]);
+        let water = water::visit(&region_graph, &water::Settings::<f32>::default()).unwrap();
+        for region in region_graph.node_indices() {
+            region_graph[region].value.ocean = water.is_ocean(region);
+        }
 
-        let (mut region_graph, border_graph) = gen_dual_graph::<TestInner, (), rand_xorshift::XorShiftRng>(dims, 8000, 2, &mut rng);
+        draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let elevation = region.value.elevation();
+            let color = if region.value.ocean { 0 } else { (255.0 / (1.0 - elevation)) as u8 };
+            image::Rgb([color, color, color])
+        });
 
-        // Start at the center
-        let center = Point2::from(dims / 2.0);
+        imgbuf.save("output/island.png").unwrap();
+    }
+
+    /// Seeded region and border graphs of the 1024 by 1024 test map, with the rng the test continues from.
+    pub(crate) fn test_graph<V: Default>() -> (RegionGraph<V>, BorderGraph, XorShiftRng) {
+        let mut rng = XorShiftRng::from_seed([122, 154, 21, 182, 159, 131, 187, 243, 134, 230, 110, 10, 31, 174, 6, 4]);
+        let (region_graph, border_graph) = gen_dual_graph::<V, (), XorShiftRng>(Vector2::new(1024.0, 1024.0), 8000, 2, &mut rng);
+        (region_graph, border_graph, rng)
+    }
+
+    /// Raises the island of the test map from twenty random peaks around the center.
+    pub(crate) fn raise_island<V: Default + HasElevation<f32>>(region_graph: &mut RegionGraph<V>, rng: &mut XorShiftRng) {
+        let center = Point2::new(512.0, 512.0);
         let soft_points = (0..20)
             .map(|_| {
                 let height = rng.gen_range(0.3, 0.8);
                 let x = rng.gen_range(-500.0, 500.0);
                 let y = rng.gen_range(-500.0, 500.0);
                 PeakNode {
-                    node: node_for_coordinate(&region_graph, Point2::new(center.x + x, center.y + y)).expect("wut"),
+                    node: node_for_coordinate(region_graph, Point2::new(center.x + x, center.y + y)).expect("wut"),
                     elevation: height,
                 }
             })
             .collect::<Vec<_>>();
 
         let settings = Settings::<f32>::default().with_peak_nodes(soft_points);
+        visit(region_graph, &settings, rng).unwrap();
+    }
 
-        visit(&mut region_graph, &settings, &mut rng).unwrap();
+    /// The test map with its island raised, shared by the stage tests so they all run on the same terrain.
+    pub(crate) fn island<V: Default + HasElevation<f32>>() -> (RegionGraph<V>, BorderGraph, XorShiftRng) {
+        let (mut region_graph, border_graph, mut rng) = test_graph();
+        raise_island(&mut region_graph, &mut rng);
+        (region_graph, border_graph, rng)
+    }
 
-        draw_graph(&mut imgbuf, &region_graph, &border_graph, |region, border_graph| {
-            //let color = region.value.height as i32;
-            let elevation = region.value.elevation();
-            let color = if elevation > 0.3 { (255.0 / (1.0 - elevation)) as u8 } else { 0 };
+    /// Blank image the size of the test map.
+    pub(crate) fn canvas() -> image::RgbImage {
+        image::ImageBuffer::from_pixel(1024, 1024, image::Rgb([222, 222, 222]))
+    }
 
+    /// Fills every region with the color `region_color` picks for it.
+    pub(crate) fn draw_regions<V, F>(imgbuf: &mut image::RgbImage, region_graph: &RegionGraph<V>, border_graph: &BorderGraph, region_color: F)
+    where
+        F: Fn(&RegionNode<V>) -> image::Rgb<u8>,
+    {
+        draw_graph(imgbuf, region_graph, border_graph, |region, border_graph| {
             (
-                image::Rgb([color, color, color]),
+                region_color(region),
                 region
                     .borders
                     .iter()
-                    .filter_map(|idx| {
+                    .map(|idx| {
                         let node = border_graph.node_weight(*idx).expect("Bad graphs");
-
-                        Some(ImgPoint::<i32>::new(node.pos.x as i32, node.pos.y as i32))
+                        ImgPoint::<i32>::new(node.pos.x as i32, node.pos.y as i32)
                     })
                     .collect(),
             )
         });
-
-        imgbuf.save("output/island.png").unwrap();
     }
 
     pub(crate) fn draw_graph<
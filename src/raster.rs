@@ -0,0 +1,347 @@
+//! Raster
+//! Renders the elevation of a region graph into a continuous heightmap, either an `f32` grid or a 16-bit grayscale
+//! image, at any resolution. Pixels are placed over the bounds of the border graph and interpolated from the regions
+//! around them: flat per region, barycentric over the Delaunay triangles, natural neighbor, or radial basis functions
+//! over the closest region and its neighbors. Rows are rendered in parallel.
+//!
+use crate::{
+    dual_graph::{bounds, BorderEdge, BorderNode, RegionEdge, RegionNode, RegionNodeIdx},
+    spatial::SpatialIndex,
+    HasElevation,
+};
+use image::{ImageBuffer, Luma};
+use nalgebra::{Point2, RealField};
+use petgraph::{EdgeType, Graph};
+use rayon::prelude::*;
+use smallvec::SmallVec;
+use spade::{
+    delaunay::{DelaunayTreeLocate, DelaunayTriangulation},
+    kernels::FloatKernel,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Interpolation {
+    /// Every pixel takes the elevation of the region it lies in.
+    Flat,
+    /// Linear over the triangles between neighboring region positions.
+    Barycentric,
+    /// Sibson natural neighbor interpolation, smooth everywhere except at the region positions.
+    NaturalNeighbor,
+    /// Radial basis functions fitted to the closest region and its neighbors.
+    Rbf,
+}
+
+/// Row-major grid of elevations.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f32>,
+}
+impl Grid {
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.values[(y * self.width + x) as usize]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+    low: T,
+    high: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_resolution(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Elevations mapped to black and white when rendering an image.
+    pub fn with_range(mut self, low: T, high: T) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 1024,
+            interpolation: Interpolation::Barycentric,
+            low: 0.0.into(),
+            high: 1.0.into(),
+        }
+    }
+}
+
+struct Site {
+    pos: Point2<f32>,
+    elevation: f32,
+}
+impl spade::HasPosition for Site {
+    type Point = Point2<f32>;
+
+    fn position(&self) -> Point2<f32> {
+        self.pos
+    }
+}
+
+/// Triangles of the Delaunay triangulation, taken from the regions meeting at every corner of the border graph.
+/// Corners shared by more than three regions are split into a fan.
+fn triangles<V, B, E: EdgeType>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+) -> (Vec<[RegionNodeIdx; 3]>, Vec<Vec<usize>>) {
+    let mut triangles = Vec::new();
+    let mut incident = vec![Vec::new(); region_graph.node_count()];
+
+    for corner in border_graph.raw_nodes() {
+        let mut regions = corner.weight.regions.clone();
+        if regions.len() < 3 {
+            continue;
+        }
+        let center = corner.weight.pos;
+        regions.sort_by(|a, b| {
+            let (a, b) = (region_graph[*a].pos - center, region_graph[*b].pos - center);
+            a.y.atan2(a.x).partial_cmp(&b.y.atan2(b.x)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        for i in 1..regions.len() - 1 {
+            let triangle = [regions[0], regions[i], regions[i + 1]];
+            for region in &triangle {
+                incident[region.index()].push(triangles.len());
+            }
+            triangles.push(triangle);
+        }
+    }
+
+    (triangles, incident)
+}
+
+/// Barycentric weights of the point in the triangle, `None` if it lies outside.
+fn barycentric(triangle: [Point2<f32>; 3], point: Point2<f32>) -> Option<[f32; 3]> {
+    let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+    let determinant = (b.y - c.y) * (a.x - c.x) + (c.x - b.x) * (a.y - c.y);
+    if determinant.abs() <= std::f32::EPSILON {
+        return None;
+    }
+
+    let alpha = ((b.y - c.y) * (point.x - c.x) + (c.x - b.x) * (point.y - c.y)) / determinant;
+    let beta = ((c.y - a.y) * (point.x - c.x) + (a.x - c.x) * (point.y - c.y)) / determinant;
+    let gamma = 1.0 - alpha - beta;
+    let tolerance = -1.0e-4;
+    if alpha >= tolerance && beta >= tolerance && gamma >= tolerance {
+        Some([alpha, beta, gamma])
+    } else {
+        None
+    }
+}
+
+/// Interpolates an elevation for every pixel, returned as a grid.
+pub fn to_grid<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    settings: &Settings<T>,
+) -> Result<Grid, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + Sync,
+    B: Sync,
+    E: EdgeType + Sync,
+{
+    if settings.width == 0 || settings.height == 0 {
+        return Err(failure::format_err!("Raster resolution must not be zero"));
+    }
+    let (low, high) = bounds(border_graph).ok_or_else(|| failure::format_err!("Border graph has no area to rasterize"))?;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let elevations = region_graph
+        .node_indices()
+        .map(|region| nalgebra::try_convert::<T, f64>(region_graph[region].value.elevation()).unwrap_or(0.0) as f32)
+        .collect::<Vec<_>>();
+    let index = SpatialIndex::new(region_graph);
+
+    let (triangles, incident) = match settings.interpolation {
+        Interpolation::Barycentric => triangles(region_graph, border_graph),
+        _ => (Vec::new(), Vec::new()),
+    };
+    let triangulation = match settings.interpolation {
+        Interpolation::NaturalNeighbor => {
+            let mut triangulation = DelaunayTriangulation::<_, FloatKernel, DelaunayTreeLocate<Point2<f32>>>::with_tree_locate();
+            for region in region_graph.node_indices() {
+                triangulation.insert(Site {
+                    pos: region_graph[region].pos,
+                    elevation: elevations[region.index()],
+                });
+            }
+            Some(triangulation)
+        }
+        _ => None,
+    };
+
+    let interpolation = settings.interpolation;
+    let sample = |point: Point2<f32>| -> f32 {
+        let nearest = match index.nearest(point) {
+            Some(nearest) => nearest,
+            None => return 0.0,
+        };
+        let flat = elevations[nearest.index()];
+
+        match interpolation {
+            Interpolation::Flat => flat,
+            Interpolation::Barycentric => {
+                // The containing triangle almost always touches the closest region, otherwise one of its neighbors
+                std::iter::once(nearest)
+                    .chain(region_graph.neighbors(nearest))
+                    .flat_map(|region| incident[region.index()].iter())
+                    .find_map(|triangle| {
+                        let corners = triangles[*triangle];
+                        let positions = [region_graph[corners[0]].pos, region_graph[corners[1]].pos, region_graph[corners[2]].pos];
+                        barycentric(positions, point).map(|weights| {
+                            weights
+                                .iter()
+                                .zip(&corners)
+                                .fold(0.0, |acc, (weight, region)| acc + weight * elevations[region.index()])
+                        })
+                    })
+                    .unwrap_or(flat)
+            }
+            Interpolation::NaturalNeighbor => triangulation
+                .as_ref()
+                .and_then(|triangulation| triangulation.nn_interpolation(&point, |site| site.elevation))
+                .unwrap_or(flat),
+            Interpolation::Rbf => {
+                use rbf_interp::{DistanceFunction, PtValue, Rbf};
+
+                let mut points = SmallVec::<[PtValue<f32>; 32]>::default();
+                for region in std::iter::once(nearest).chain(region_graph.neighbors(nearest)) {
+                    let pos = region_graph[region].pos;
+                    points.push(PtValue::new(pos.x, pos.y, elevations[region.index()]));
+                }
+                Rbf::new(&points, DistanceFunction::Linear, None).interp_point((point.x, point.y))
+            }
+        }
+    };
+
+    let (width, height) = (settings.width, settings.height);
+    #[allow(clippy::cast_precision_loss)]
+    let scale = ((high.x - low.x) / width as f32, (high.y - low.y) / height as f32);
+
+    let mut values = vec![0.0; (width * height) as usize];
+    values.par_chunks_mut(width as usize).enumerate().for_each(|(y, row)| {
+        for (x, value) in row.iter_mut().enumerate() {
+            // Sample at pixel centers
+            #[allow(clippy::cast_precision_loss)]
+            let point = Point2::new(low.x + (x as f32 + 0.5) * scale.0, low.y + (y as f32 + 0.5) * scale.1);
+            *value = sample(point);
+        }
+    });
+
+    Ok(Grid { width, height, values })
+}
+
+/// Interpolates the elevations into a 16-bit grayscale image, mapping the settings range to the full range of values.
+pub fn to_image<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    settings: &Settings<T>,
+) -> Result<ImageBuffer<Luma<u16>, Vec<u16>>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + Sync,
+    B: Sync,
+    E: EdgeType + Sync,
+{
+    let range = settings.high - settings.low;
+    if range <= T::zero() {
+        return Err(failure::format_err!("Raster elevation range must not be empty"));
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let low = nalgebra::try_convert::<T, f64>(settings.low).unwrap_or(0.0) as f32;
+    #[allow(clippy::cast_possible_truncation)]
+    let range = nalgebra::try_convert::<T, f64>(range).unwrap_or(1.0) as f32;
+
+    let grid = to_grid(region_graph, border_graph, settings)?;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let pixels = grid
+        .values
+        .iter()
+        .map(|value| {
+            ((value - low) / range * f32::from(std::u16::MAX))
+                .round()
+                .max(0.0)
+                .min(f32::from(std::u16::MAX)) as u16
+        })
+        .collect();
+
+    ImageBuffer::from_raw(grid.width, grid.height, pixels).ok_or_else(|| failure::format_err!("Raster does not fit the image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn raster_interpolation() {
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        // Barycentric interpolation reproduces a plane wherever the pixel is inside the triangulation, on the same map
+        let (mut plane, _, _) = peak_automata::tests::test_graph::<TestInner>();
+        for region in plane.node_weights_mut() {
+            let elevation = region.pos.x / 1024.0;
+            region.value.set_elevation(elevation);
+        }
+        let settings = Settings::<f32>::default().with_resolution(128, 128);
+        let grid = to_grid(&plane, &border_graph, &settings).unwrap();
+        assert_eq!(grid.values.len(), 128 * 128);
+        for y in 16..112 {
+            for x in 16..112 {
+                assert!((grid.get(x, y) - (x as f32 + 0.5) / 128.0).abs() < 1.0e-3);
+            }
+        }
+
+        let (low, high) = region_graph
+            .node_weights_mut()
+            .fold((std::f32::MAX, std::f32::MIN), |(low, high), region| {
+                (low.min(region.value.elevation()), high.max(region.value.elevation()))
+            });
+        for (interpolation, name) in &[
+            (Interpolation::Flat, "flat"),
+            (Interpolation::Barycentric, "barycentric"),
+            (Interpolation::NaturalNeighbor, "natural_neighbor"),
+            (Interpolation::Rbf, "rbf"),
+        ] {
+            let settings = Settings::<f32>::default()
+                .with_resolution(512, 512)
+                .with_interpolation(*interpolation)
+                .with_range(low, high);
+            let image = to_image(&region_graph, &border_graph, &settings).unwrap();
+            assert_eq!(image.dimensions(), (512, 512));
+            // This version of image only encodes 8-bit grayscale
+            let preview = image::GrayImage::from_fn(512, 512, |x, y| image::Luma([(image.get_pixel(x, y).0[0] >> 8) as u8]));
+            preview.save(format!("output/raster_{}.png", name)).unwrap();
+        }
+    }
+}
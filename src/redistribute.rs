@@ -0,0 +1,279 @@
+//! Redistribute
+//! Reshapes the elevation histogram so later stages do not depend on the range a generator happened to produce.
+//! Elevations are normalized to `[0, 1]`, split at a sea level, and the land and the ocean floor are each remapped by
+//! rank onto their own target distribution. The sea level can be given directly or chosen so a fraction of the map
+//! ends up as land. The order of regions by elevation is preserved.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode},
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+/// Target cumulative distribution, mapping the rank of a region within `[0, 1]` to an elevation within `[0, 1]`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Distribution<T: RealField> {
+    /// Evenly spread elevations.
+    Linear,
+    /// `rank ^ exponent`, exponents above one give more lowland.
+    Power(T),
+    /// Piecewise linear curve through `(rank, elevation)` points within `[0, 1]` with increasing ranks.
+    Curve(Vec<(T, T)>),
+}
+impl<T: RealField + From<f32>> Distribution<T> {
+    /// A curve where `fraction` of the regions lie below `height`.
+    pub fn lowland(fraction: T, height: T) -> Self {
+        Distribution::Curve(vec![(T::zero(), T::zero()), (fraction, height), (T::one(), T::one())])
+    }
+
+    fn validate(&self) -> Result<(), failure::Error> {
+        match self {
+            Distribution::Linear => Ok(()),
+            Distribution::Power(exponent) if *exponent > T::zero() => Ok(()),
+            Distribution::Power(_) => Err(failure::format_err!("Distribution exponent must be positive")),
+            Distribution::Curve(points) if points.is_empty() => Err(failure::format_err!("Distribution curve has no points")),
+            Distribution::Curve(points) => {
+                let unit = |value: T| value >= T::zero() && value <= T::one();
+                if points.iter().any(|(rank, elevation)| !unit(*rank) || !unit(*elevation)) {
+                    return Err(failure::format_err!("Distribution curve points must be within [0, 1]"));
+                }
+                if points.windows(2).any(|pair| pair[0].0 >= pair[1].0 || pair[0].1 > pair[1].1) {
+                    return Err(failure::format_err!("Distribution curve must increase"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn sample(&self, rank: T) -> T {
+        match self {
+            Distribution::Linear => rank,
+            Distribution::Power(exponent) => rank.powf(*exponent),
+            Distribution::Curve(points) => {
+                let upper = points.iter().position(|(at, _)| *at >= rank).unwrap_or(points.len() - 1);
+                if upper == 0 || points[upper].0 < rank {
+                    return points[upper].1;
+                }
+                let ((a, low), (b, high)) = (points[upper - 1], points[upper]);
+                low + (high - low) * (rank - a) / (b - a)
+            }
+        }
+    }
+}
+
+/// How the elevation separating ocean from land is chosen.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum SeaLevel<T: RealField> {
+    /// A fixed level within the normalized `[0, 1]` range.
+    Fixed(T),
+    /// The level that leaves this fraction of the regions as land.
+    LandFraction(T),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: SeaLevel<T>,
+    target_sea_level: T,
+    land: Distribution<T>,
+    ocean: Distribution<T>,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_sea_level(mut self, sea_level: SeaLevel<T>) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Elevation the sea level is moved to, land fills the range above it and ocean the range below.
+    pub fn with_target_sea_level(mut self, target_sea_level: T) -> Self {
+        self.target_sea_level = target_sea_level;
+        self
+    }
+
+    pub fn with_land(mut self, land: Distribution<T>) -> Self {
+        self.land = land;
+        self
+    }
+
+    pub fn with_ocean(mut self, ocean: Distribution<T>) -> Self {
+        self.ocean = ocean;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: SeaLevel::LandFraction(0.4.into()),
+            target_sea_level: 0.3.into(),
+            land: Distribution::Power(2.0.into()),
+            ocean: Distribution::Linear,
+        }
+    }
+}
+
+/// Linearly rescales all elevations to `[0, 1]`. A flat graph is set to zero.
+pub fn normalize<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>)
+where
+    T: RealField,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    let (low, high) = region_graph
+        .node_weights_mut()
+        .fold((None, None), |(low, high): (Option<T>, Option<T>), region| {
+            let elevation = region.value.elevation();
+            (
+                Some(low.map_or(elevation, |low| low.min(elevation))),
+                Some(high.map_or(elevation, |high| high.max(elevation))),
+            )
+        });
+    let (low, high) = match (low, high) {
+        (Some(low), Some(high)) => (low, high),
+        _ => return,
+    };
+
+    let range = high - low;
+    for region in region_graph.node_weights_mut() {
+        let elevation = if range > T::zero() {
+            (region.value.elevation() - low) / range
+        } else {
+            T::zero()
+        };
+        region.value.set_elevation(elevation);
+    }
+}
+
+/// Normalizes and redistributes the elevations, returning the normalized level that was used as sea level.
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<T, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    settings.land.validate()?;
+    settings.ocean.validate()?;
+    if settings.target_sea_level < T::zero() || settings.target_sea_level > T::one() {
+        return Err(failure::format_err!("Target sea level must be within [0, 1]"));
+    }
+    match settings.sea_level {
+        SeaLevel::Fixed(level) if level < T::zero() || level > T::one() => {
+            return Err(failure::format_err!("Sea level must be within [0, 1]"));
+        }
+        SeaLevel::LandFraction(fraction) if fraction < T::zero() || fraction > T::one() => {
+            return Err(failure::format_err!("Land fraction must be within [0, 1]"));
+        }
+        _ => {}
+    }
+
+    normalize(region_graph);
+
+    let mut order = region_graph
+        .node_indices()
+        .map(|region| (region_graph[region].value.elevation(), region))
+        .collect::<Vec<_>>();
+    order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let sea_level = match settings.sea_level {
+        SeaLevel::Fixed(level) => level,
+        SeaLevel::LandFraction(fraction) => {
+            #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let first_land =
+                nalgebra::try_convert::<T, f64>((T::one() - fraction) * T::from(order.len() as f32)).map_or(0, |rank| rank.round() as usize);
+            // Above every normalized elevation when no land is wanted
+            order.get(first_land).map_or_else(|| T::one() + T::one(), |(elevation, _)| *elevation)
+        }
+    };
+
+    let ocean = order.iter().take_while(|(elevation, _)| *elevation < sea_level).count();
+    let land = order.len() - ocean;
+    let target = settings.target_sea_level;
+
+    for (rank, (_, region)) in order.iter().enumerate() {
+        #[allow(clippy::cast_precision_loss)]
+        let elevation = if rank < ocean {
+            // Ocean ranks stop short of one so the shallowest region stays below the sea level
+            target * settings.ocean.sample(T::from(rank as f32) / T::from(ocean as f32))
+        } else {
+            let rank = T::from((rank - ocean) as f32) / T::from(land.saturating_sub(1).max(1) as f32);
+            target + (T::one() - target) * settings.land.sample(rank)
+        };
+        region_graph[*region].value.set_elevation(elevation);
+    }
+
+    Ok(sea_level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn redistribute_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        // Normalizing first keeps rounding from reordering regions with nearly equal elevations
+        normalize(&mut region_graph);
+        let mut before = region_graph
+            .node_indices()
+            .map(|region| (region_graph[region].value.elevation(), region))
+            .collect::<Vec<_>>();
+        before.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let settings = Settings::<f32>::default().with_land(Distribution::lowland(0.6, 0.4));
+        visit(&mut region_graph, &settings).unwrap();
+
+        let count = region_graph.node_count() as f32;
+        let elevations = before
+            .iter()
+            .map(|(_, region)| region_graph[*region].value.elevation())
+            .collect::<Vec<_>>();
+        assert!(elevations.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(elevations[0] >= 0.0 && elevations[elevations.len() - 1] <= 1.0);
+
+        let land = elevations.iter().filter(|elevation| **elevation >= 0.3).count() as f32;
+        assert!((land / count - 0.4).abs() < 0.01);
+        // Land below 0.3 + 0.7 * 0.4 is the lowland fraction of the land
+        let lowland = elevations.iter().filter(|elevation| **elevation >= 0.3 && **elevation < 0.58).count() as f32;
+        assert!((lowland / land - 0.6).abs() < 0.02);
+
+        assert!(visit(&mut region_graph, &Settings::<f32>::default().with_land(Distribution::Power(0.0))).is_err());
+        assert!(visit(&mut region_graph, &Settings::<f32>::default().with_land(Distribution::lowland(0.5, 1.5))).is_err());
+        assert!(visit(
+            &mut region_graph,
+            &Settings::<f32>::default().with_ocean(Distribution::Curve(vec![(-0.5, 0.0), (1.0, 1.0)]))
+        )
+        .is_err());
+        assert!(visit(&mut region_graph, &Settings::<f32>::default().with_sea_level(SeaLevel::Fixed(1.5))).is_err());
+        assert!(visit(
+            &mut region_graph,
+            &Settings::<f32>::default().with_sea_level(SeaLevel::LandFraction(-0.1))
+        )
+        .is_err());
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let elevation = region.value.elevation();
+            if elevation < 0.3 {
+                image::Rgb([0, 0, (100.0 + 400.0 * elevation) as u8])
+            } else {
+                let shade = (255.0 * elevation) as u8;
+                image::Rgb([shade, shade, shade])
+            }
+        });
+
+        imgbuf.save("output/redistribute.png").unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Rivers
+//! Builds a river network along the edges of the border graph, so rivers run between regions instead of through their
+//! centers. Every corner takes the mean elevation of the regions around it, water is routed from corner to corner down
+//! to the sea, and flux is accumulated on every border edge it passes. Edges carrying more than a threshold become
+//! river segments with a width and a Strahler order.
+//!
+use crate::{
+    dual_graph::{BorderEdge, BorderEdgeIdx, BorderNode, BorderNodeIdx, RegionEdge, RegionNode},
+    flow, HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{graph::NodeIndex, EdgeType, Graph};
+use std::collections::HashMap;
+
+/// Rivers as a directed graph of corners, with every edge pointing downstream.
+pub type RiverGraph<T> = petgraph::graph::DiGraph<BorderNodeIdx, RiverSegment<T>>;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RiverSegment<T: RealField> {
+    pub edge: BorderEdgeIdx,
+    /// Upstream corner.
+    pub from: BorderNodeIdx,
+    /// Downstream corner.
+    pub to: BorderNodeIdx,
+    pub flux: T,
+    pub width: T,
+    /// Strahler stream order, 1 for headwaters.
+    pub order: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rivers<T: RealField> {
+    corner_elevations: Vec<T>,
+    flux: Vec<T>,
+    segments: HashMap<BorderEdgeIdx, NodeIndex>,
+    corners: HashMap<BorderNodeIdx, NodeIndex>,
+    graph: RiverGraph<T>,
+}
+impl<T: RealField> Rivers<T> {
+    /// Mean elevation of the regions around a corner.
+    pub fn corner_elevation(&self, corner: BorderNodeIdx) -> T {
+        self.corner_elevations[corner.index()]
+    }
+
+    /// Water passing along a border edge, zero if no water is routed along it.
+    pub fn flux(&self, edge: BorderEdgeIdx) -> T {
+        self.flux[edge.index()]
+    }
+
+    /// River segment running along a border edge, if the edge carries a river.
+    pub fn segment(&self, edge: BorderEdgeIdx) -> Option<&RiverSegment<T>> {
+        self.graph
+            .edges_directed(self.segments.get(&edge).copied()?, petgraph::Direction::Outgoing)
+            .find_map(|segment| if segment.weight().edge == edge { Some(segment.weight()) } else { None })
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = &RiverSegment<T>> {
+        self.graph.raw_edges().iter().map(|edge| &edge.weight)
+    }
+
+    /// Node of the river graph at a corner, if a river touches it.
+    pub fn corner(&self, corner: BorderNodeIdx) -> Option<NodeIndex> {
+        self.corners.get(&corner).copied()
+    }
+
+    pub fn graph(&self) -> &RiverGraph<T> {
+        &self.graph
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: T,
+    rain: T,
+    threshold: T,
+    width: T,
+    epsilon: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Corners touching a region below this elevation drain into the sea.
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Water contributed by every land corner.
+    pub fn with_rain(mut self, rain: T) -> Self {
+        self.rain = rain;
+        self
+    }
+
+    /// Minimum flux for a border edge to become a river segment.
+    pub fn with_threshold(mut self, threshold: T) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// River width per square root of flux.
+    pub fn with_width(mut self, width: T) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Gradient enforced when filling depressions between corners, see `flow::Settings::with_epsilon`.
+    pub fn with_epsilon(mut self, epsilon: T) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: 0.3.into(),
+            rain: 1.0.into(),
+            threshold: 20.0.into(),
+            width: 0.5.into(),
+            epsilon: 0.00001.into(),
+        }
+    }
+}
+
+pub fn visit<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    settings: &Settings<T>,
+) -> Result<Rivers<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    let mut corner_elevations = Vec::with_capacity(border_graph.node_count());
+    let mut outlets = Vec::with_capacity(border_graph.node_count());
+    for corner in border_graph.node_indices() {
+        let regions = &border_graph[corner].regions;
+        if regions.is_empty() {
+            return Err(failure::format_err!("Border node {:?} has no regions", corner));
+        }
+
+        let mut elevations = regions.iter().map(|region| region_graph[*region].value.elevation());
+        #[allow(clippy::cast_precision_loss)]
+        let mean = elevations.clone().fold(T::zero(), |acc, elevation| acc + elevation) / T::from(regions.len() as f32);
+        corner_elevations.push(mean);
+
+        // Corners on the coast or on the edge of the map let water leave the graph
+        let coast = elevations.any(|elevation| elevation < settings.sea_level);
+        let edge = border_graph.edges(corner).any(|edge| edge.weight().regions.len() < 2);
+        outlets.push(coast || edge);
+    }
+
+    let routing = flow::route_borders(
+        border_graph,
+        &corner_elevations,
+        &outlets,
+        &flow::Settings::default().with_epsilon(settings.epsilon),
+    );
+    let rain = outlets
+        .iter()
+        .map(|outlet| if *outlet { T::zero() } else { settings.rain })
+        .collect::<Vec<_>>();
+    let accumulation = routing.accumulate(&rain);
+
+    let mut flux = vec![T::zero(); border_graph.edge_count()];
+    let mut graph = RiverGraph::new();
+    let mut corners = HashMap::new();
+    let mut segments = HashMap::new();
+    // Highest order and how often it arrived at every corner, for Strahler ordering
+    let mut inflow = vec![(0, 0); border_graph.node_count()];
+
+    for corner in routing.upstream_order() {
+        let next = match routing.receiver(corner) {
+            Some(next) if !outlets[corner.index()] => next,
+            _ => continue,
+        };
+        let (edge, _) = border_graph
+            .find_edge_undirected(corner, next)
+            .ok_or_else(|| failure::format_err!("Corners {:?} and {:?} are not connected", corner, next))?;
+        let amount = accumulation[corner.index()];
+        flux[edge.index()] = amount;
+
+        if amount < settings.threshold {
+            continue;
+        }
+
+        let order = match inflow[corner.index()] {
+            (0, _) => 1,
+            (highest, count) if count >= 2 => highest + 1,
+            (highest, _) => highest,
+        };
+        let downstream = &mut inflow[next.index()];
+        if order > downstream.0 {
+            *downstream = (order, 1);
+        } else if order == downstream.0 {
+            downstream.1 += 1;
+        }
+
+        let from = *corners.entry(corner).or_insert_with(|| graph.add_node(corner));
+        let to = *corners.entry(next).or_insert_with(|| graph.add_node(next));
+        graph.add_edge(
+            from,
+            to,
+            RiverSegment {
+                edge,
+                from: corner,
+                to: next,
+                flux: amount,
+                width: settings.width * amount.sqrt(),
+                order,
+            },
+        );
+        segments.insert(edge, from);
+    }
+
+    Ok(Rivers {
+        corner_elevations,
+        flux,
+        segments,
+        corners,
+        graph,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn rivers_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        let rivers = visit(&region_graph, &border_graph, &Settings::<f32>::default()).unwrap();
+
+        assert!(rivers.segments().count() > 0);
+        for segment in rivers.segments() {
+            assert!(segment.flux >= 20.0 && segment.order >= 1);
+            assert!((rivers.flux(segment.edge) - segment.flux).abs() < std::f32::EPSILON);
+            assert_eq!(rivers.segment(segment.edge).map(|found| found.from), Some(segment.from));
+            // Flux and order never decrease downstream
+            if let Some(next) = rivers.corner(segment.to) {
+                for downstream in rivers.graph().edges_directed(next, petgraph::Direction::Outgoing) {
+                    assert!(downstream.weight().flux >= segment.flux);
+                    assert!(downstream.weight().order >= segment.order);
+                }
+            }
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let elevation = region.value.elevation();
+            if elevation < 0.3 {
+                image::Rgb([0, 0, 120])
+            } else {
+                let shade = (255.0 * elevation.min(1.0)) as u8;
+                image::Rgb([shade, shade, shade])
+            }
+        });
+        for segment in rivers.segments() {
+            let (from, to) = (border_graph[segment.from].pos, border_graph[segment.to].pos);
+            for offset in 0..(segment.width as i32).max(1) {
+                imageproc::drawing::draw_antialiased_line_segment_mut(
+                    &mut imgbuf,
+                    (from.x as i32 + offset, from.y as i32),
+                    (to.x as i32 + offset, to.y as i32),
+                    image::Rgb([0, 80, 222]),
+                    imageproc::pixelops::interpolate,
+                );
+            }
+        }
+
+        imgbuf.save("output/rivers.png").unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+use std::cmp::Ordering;
+
+/// Pairs a score with a value for use in a `BinaryHeap`, comparing in reverse score order so the heap pops the lowest
+/// score first. NaN scores are ordered last.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct MinScored<K, T>(pub K, pub T);
+
+impl<K: PartialOrd, T> PartialEq for MinScored<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: PartialOrd, T> Eq for MinScored<K, T> {}
+
+impl<K: PartialOrd, T> PartialOrd for MinScored<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: PartialOrd, T> Ord for MinScored<K, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let Some(ordering) = other.0.partial_cmp(&self.0) {
+            return ordering;
+        }
+
+        #[allow(clippy::eq_op)]
+        let (self_nan, other_nan) = (self.0 != self.0, other.0 != other.0);
+        match (self_nan, other_nan) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+}
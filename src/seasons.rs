@@ -0,0 +1,339 @@
+//! Seasons
+//! Steps the climate through the year. The subsolar latitude swings between the tropics set by the axial tilt, moving
+//! the circulation bands and the insolation with it, and every step runs the wind, temperature and moisture stages for
+//! that time of year. Temperature, precipitation and wind are accumulated into monthly statistics per region, which is
+//! what climate classification needs. At the end the annual means are written to the regions.
+//!
+use crate::{
+    currents::Currents,
+    distance,
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    moisture, simple_wind, temperature, water, AnnualRainfall, HasElevation, HasMoisture, HasTemperature, HasWind,
+};
+use nalgebra::{RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+pub const MONTHS: usize = 12;
+
+/// Axial tilt of the Earth in degrees, the tilt the seasonal temperature ranges are calibrated for.
+const EARTH_TILT: f32 = 23.44;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Month<T: RealField> {
+    /// Mean temperature in degrees Celsius.
+    pub temperature: T,
+    /// Precipitation in millimeters.
+    pub precipitation: T,
+    /// Mean wind vector.
+    pub wind: Vector2<T>,
+}
+impl<T: RealField> Month<T> {
+    /// Direction the prevailing wind blows towards, in radians from the positive x axis.
+    pub fn wind_direction(&self) -> T {
+        self.wind.y.atan2(self.wind.x)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Seasons<T: RealField> {
+    months: Vec<[Month<T>; MONTHS]>,
+}
+impl<T: RealField> Seasons<T> {
+    /// Number of regions the seasons were simulated for.
+    pub fn region_count(&self) -> usize {
+        self.months.len()
+    }
+
+    /// Months of the year starting in January, the northern winter.
+    pub fn months(&self, region: RegionNodeIdx) -> &[Month<T>; MONTHS] {
+        &self.months[region.index()]
+    }
+
+    pub fn month(&self, region: RegionNodeIdx, month: usize) -> Month<T> {
+        self.months[region.index()][month]
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn annual_temperature(&self, region: RegionNodeIdx) -> T
+    where
+        T: From<f32>,
+    {
+        self.months(region).iter().fold(T::zero(), |sum, month| sum + month.temperature) / T::from(MONTHS as f32)
+    }
+
+    pub fn annual_precipitation(&self, region: RegionNodeIdx) -> T {
+        self.months(region).iter().fold(T::zero(), |sum, month| sum + month.precipitation)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    axial_tilt: T,
+    year_length: f32,
+    duration: f32,
+    step: f32,
+    lag: f32,
+    band_shift: T,
+    iterations: usize,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Axial tilt in degrees, zero removes the seasons.
+    pub fn with_axial_tilt(mut self, axial_tilt: T) -> Self {
+        self.axial_tilt = axial_tilt;
+        self
+    }
+
+    /// Days in a year, split into twelve equal months.
+    pub fn with_year_length(mut self, year_length: f32) -> Self {
+        self.year_length = year_length;
+        self
+    }
+
+    /// Days to simulate, at least one year. Months sampled more than once are averaged.
+    pub fn with_duration(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Days advanced per step, at most one month.
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Days the temperature lags behind the insolation.
+    pub fn with_lag(mut self, lag: f32) -> Self {
+        self.lag = lag;
+        self
+    }
+
+    /// Fraction of the subsolar latitude the circulation bands follow.
+    pub fn with_band_shift(mut self, band_shift: T) -> Self {
+        self.band_shift = band_shift;
+        self
+    }
+
+    /// Wind and moisture transport steps per season step. The first step runs the transport to its own limits to
+    /// spin the climate up, later steps continue from the previous one.
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            axial_tilt: EARTH_TILT.into(),
+            year_length: 365.0,
+            duration: 365.0,
+            step: 7.0,
+            lag: 30.0,
+            band_shift: 0.5.into(),
+            iterations: 20,
+        }
+    }
+}
+
+/// Simulates the settings duration, which must cover at least a year, and returns the monthly statistics. Given ocean
+/// `currents`, the temperature and moisture stages take their sea surface anomaly into account.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+pub fn visit<T, V, R, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    wind: &simple_wind::Settings<T>,
+    temperature: &temperature::Settings<T>,
+    moisture: &moisture::Settings<T>,
+    currents: Option<&Currents<T>>,
+    settings: &Settings<T>,
+    rng: &mut R,
+) -> Result<Seasons<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T> + HasWind<T> + HasTemperature<T> + HasMoisture<T> + AnnualRainfall<T>,
+    R: rand::Rng + ?Sized,
+    E: EdgeType,
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    let year = settings.year_length;
+    let duration = settings.duration;
+    if year <= 0.0 {
+        return Err(failure::format_err!("Year length must be positive"));
+    }
+    if settings.step <= 0.0 || settings.step > year / MONTHS as f32 {
+        return Err(failure::format_err!("Season step must be positive and at most one month"));
+    }
+    if duration < year {
+        return Err(failure::format_err!("Seasonal simulation must cover at least a year"));
+    }
+
+    let count = region_graph.node_count();
+    let latitudes = temperature.latitude().visit(region_graph);
+    let tilt_ratio = settings.axial_tilt / T::from(EARTH_TILT);
+    // Northern spring equinox
+    let equinox = year * 79.0 / 365.0;
+
+    let mut temperatures = vec![[T::zero(); MONTHS]; count];
+    let mut rainfall = vec![[T::zero(); MONTHS]; count];
+    let mut winds = vec![[Vector2::zeros(); MONTHS]; count];
+    let mut samples = [0_usize; MONTHS];
+
+    // The terrain does not change during the year
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(temperature.sea_level()))?;
+    let coast = distance::from_coast(region_graph, &water, &distance::Settings::default())?;
+    let moisture_water = water::visit(region_graph, &water::Settings::default().with_sea_level(moisture.sea_level()))?;
+
+    let steps = (duration / settings.step).ceil() as usize;
+    for step in 0..steps {
+        let day = (step as f32 + 0.5) * settings.step;
+        let month = ((day % year) / year * MONTHS as f32) as usize % MONTHS;
+        let phase = |lag: f32| T::from((2.0 * std::f32::consts::PI * (day - equinox - lag) / year).sin());
+
+        // The circulation follows the sun, with a fixed prevailing wind there is nothing to move
+        let subsolar = settings.axial_tilt * phase(0.0);
+        let mut step_wind = match wind.circulation() {
+            Some(circulation) => wind
+                .clone()
+                .with_circulation(circulation.clone().with_shift(subsolar * settings.band_shift)),
+            None => wind.clone(),
+        };
+        let mut step_moisture = moisture.clone();
+        if step > 0 {
+            step_wind = step_wind.with_max_steps(settings.iterations).with_warm_start(true);
+            step_moisture = step_moisture.with_steps(settings.iterations).with_warm_start(true);
+        }
+        simple_wind::visit(region_graph, &step_wind, rng)?;
+
+        // Summer in the northern hemisphere is winter in the southern one
+        let annual = temperature::visit_with_water(region_graph, temperature, &water, &coast, currents)?;
+        let season = phase(settings.lag) * tilt_ratio;
+        for region in region_graph.node_indices() {
+            let hemisphere = if latitudes.latitude(region) < T::zero() { -T::one() } else { T::one() };
+            let amplitude = (annual.warmest(region) - annual.coldest(region)) * T::from(0.5);
+            region_graph[region]
+                .value
+                .set_temperature(annual.mean(region) + amplitude * season * hemisphere);
+        }
+
+        let precipitation = moisture::visit_with_water(region_graph, &step_moisture, &moisture_water, currents)?;
+
+        for region in region_graph.node_indices() {
+            let value = &region_graph[region].value;
+            temperatures[region.index()][month] += value.temperature();
+            rainfall[region.index()][month] += precipitation.rainfall(region);
+            winds[region.index()][month] += value.wind_vector();
+        }
+        samples[month] += 1;
+    }
+
+    // Rainfall is an annual rate, a month receives a twelfth of its mean
+    let months = T::from(MONTHS as f32);
+    let seasons = Seasons {
+        months: (0..count)
+            .map(|region| {
+                let mut record = [Month {
+                    temperature: T::zero(),
+                    precipitation: T::zero(),
+                    wind: Vector2::zeros(),
+                }; MONTHS];
+                for (month, statistics) in record.iter_mut().enumerate() {
+                    let samples = T::from(samples[month].max(1) as f32);
+                    statistics.temperature = temperatures[region][month] / samples;
+                    statistics.precipitation = rainfall[region][month] / samples / months;
+                    statistics.wind = winds[region][month] / samples;
+                }
+                record
+            })
+            .collect(),
+    };
+
+    for region in region_graph.node_indices() {
+        let wind = seasons.months(region).iter().fold(Vector2::zeros(), |sum, month| sum + month.wind) / months;
+        let value = &mut region_graph[region].value;
+        value.set_temperature(seasons.annual_temperature(region));
+        value.set_annual_rainfall(seasons.annual_precipitation(region));
+        value.set_wind_vector(wind);
+    }
+
+    Ok(seasons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currents;
+    use crate::peak_automata::{self, node_for_coordinate, tests::ClimateInner};
+    use crate::simple_wind::Circulation;
+    use nalgebra::Point2;
+
+    type TestInner = ClimateInner;
+
+    #[test]
+    pub fn seasons_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
+
+        let north = node_for_coordinate(&region_graph, Point2::new(512.0, 200.0)).unwrap();
+        let south = node_for_coordinate(&region_graph, Point2::new(512.0, 824.0)).unwrap();
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        let temperature = temperature::Settings::<f32>::default();
+        let moisture = moisture::Settings::<f32>::default();
+        let settings = Settings::<f32>::default().with_step(365.0 / 12.0);
+        let seasons = visit(&mut region_graph, &wind, &temperature, &moisture, None, &settings, &mut rng).unwrap();
+
+        // July is summer in the north and winter in the south
+        assert!(seasons.month(north, 6).temperature > seasons.month(north, 0).temperature + 5.0);
+        assert!(seasons.month(south, 6).temperature < seasons.month(south, 0).temperature - 5.0);
+        for region in region_graph.node_indices() {
+            assert!(seasons.months(region).iter().all(|month| month.precipitation >= 0.0));
+            let value = &region_graph[region].value;
+            assert!((value.temperature() - seasons.annual_temperature(region)).abs() < 1.0e-3);
+            assert!((value.annual_rainfall() - seasons.annual_precipitation(region)).abs() < 1.0e-2);
+        }
+
+        // The warmest current warms the water it flows through
+        let currents = currents::visit(&region_graph, &currents::Settings::default()).unwrap();
+        let warmest = region_graph
+            .node_indices()
+            .max_by(|a, b| currents.anomaly(*a).partial_cmp(&currents.anomaly(*b)).unwrap())
+            .unwrap();
+        let warmed = visit(&mut region_graph, &wind, &temperature, &moisture, Some(&currents), &settings, &mut rng).unwrap();
+        assert!(currents.anomaly(warmest) > 0.0);
+        assert!(warmed.annual_temperature(warmest) > seasons.annual_temperature(warmest));
+
+        // Without a tilt every month is the same
+        let flat = visit(
+            &mut region_graph,
+            &wind,
+            &temperature,
+            &moisture,
+            None,
+            &settings.clone().with_axial_tilt(0.0).with_duration(365.0),
+            &mut rng,
+        )
+        .unwrap();
+        for month in flat.months(north) {
+            assert!((month.temperature - flat.month(north, 0).temperature).abs() < 1.0e-3);
+        }
+        assert!(visit(
+            &mut region_graph,
+            &wind,
+            &temperature,
+            &moisture,
+            None,
+            &settings.clone().with_duration(100.0),
+            &mut rng
+        )
+        .is_err());
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let t = ((region.value.temperature() + 30.0) / 60.0).min(1.0).max(0.0);
+            let wet = (region.value.annual_rainfall() / 3000.0).min(1.0).max(0.0);
+            if region.value.elevation() < 0.3 {
+                image::Rgb([0, 0, 128])
+            } else {
+                image::Rgb([(255.0 * t) as u8, (255.0 * wet) as u8, (255.0 * (1.0 - t)) as u8])
+            }
+        });
+
+        imgbuf.save("output/seasons.png").unwrap();
+    }
+}
@@ -1,38 +1,407 @@
+//! Simple Wind
+//! Propagates a prevailing wind across the region graph. Wind enters the map from the `start_from` side at
+//! `start_speed`, or continues from the wind already on the regions when asked to, and every step each region takes
+//! the wind arriving from its upwind neighbors. Air blowing uphill is slowed and turned along the contour lines, so it
+//! flows around mountains, and air blowing downhill speeds up. Away from the terrain the wind relaxes back to the
+//! prevailing direction. On larger maps the prevailing wind can instead follow the global circulation cells by
+//! latitude: trade winds, westerlies and polar easterlies.
+//!
 use crate::{
-    dual_graph::{RegionEdge, RegionNode},
-    HasElevation, HasTemperature, HasWind,
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    latitude::{Latitude, Latitudes},
+    slope, HasElevation, HasWind,
 };
 use nalgebra::{RealField, Vector2};
 use petgraph::{EdgeType, Graph};
+use smallvec::SmallVec;
 
+/// Fraction of the band speed left at the calm edges of a circulation band, such as the doldrums and horse latitudes.
+const CALM: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Band {
+    Trades,
+    Westerlies,
+    PolarEasterlies,
+}
+
+/// Prevailing winds of the three circulation cells of each hemisphere.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Circulation<T: RealField> {
+    latitude: Latitude<T>,
+    trade_edge: T,
+    polar_edge: T,
+    trade_speed: T,
+    westerly_speed: T,
+    polar_speed: T,
+    turning: T,
+    shift: T,
+}
+impl<T: RealField + From<f32>> Circulation<T> {
+    pub fn with_latitude(mut self, latitude: Latitude<T>) -> Self {
+        self.latitude = latitude;
+        self
+    }
+
+    /// Latitudes in degrees where the trade winds meet the westerlies, and the westerlies meet the polar easterlies.
+    pub fn with_band_edges(mut self, trade_edge: T, polar_edge: T) -> Self {
+        self.trade_edge = trade_edge;
+        self.polar_edge = polar_edge;
+        self
+    }
+
+    /// Peak speed within each band.
+    pub fn with_speeds(mut self, trade_speed: T, westerly_speed: T, polar_speed: T) -> Self {
+        self.trade_speed = trade_speed;
+        self.westerly_speed = westerly_speed;
+        self.polar_speed = polar_speed;
+        self
+    }
+
+    /// Angle in degrees between the winds and the east west axis, towards the equator for easterlies and towards the
+    /// poles for westerlies.
+    pub fn with_turning(mut self, turning: T) -> Self {
+        self.turning = turning;
+        self
+    }
+
+    /// Latitude in degrees the bands are centered on instead of the equator, the seasons move it towards the summer
+    /// hemisphere.
+    pub fn with_shift(mut self, shift: T) -> Self {
+        self.shift = shift;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            latitude: Latitude::default(),
+            trade_edge: 30.0.into(),
+            polar_edge: 60.0.into(),
+            trade_speed: 6.0.into(),
+            westerly_speed: 8.0.into(),
+            polar_speed: 5.0.into(),
+            turning: 30.0.into(),
+            shift: 0.0.into(),
+        }
+    }
+
+    pub fn latitude(&self) -> &Latitude<T> {
+        &self.latitude
+    }
+
+    pub fn band(&self, latitude: T) -> Band {
+        let latitude = (latitude - self.shift).abs();
+        if latitude < self.trade_edge {
+            Band::Trades
+        } else if latitude < self.polar_edge {
+            Band::Westerlies
+        } else {
+            Band::PolarEasterlies
+        }
+    }
+
+    /// Prevailing wind at a latitude in degrees, oriented by the north and east of `latitudes`.
+    pub fn wind(&self, latitude: T, latitudes: &Latitudes<T>) -> Vector2<T> {
+        let band = self.band(latitude);
+        let latitude = latitude - self.shift;
+        let hemisphere = if latitude < T::zero() { -T::one() } else { T::one() };
+        let (low, high, speed, east) = match band {
+            Band::Trades => (T::zero(), self.trade_edge, self.trade_speed, -T::one()),
+            Band::Westerlies => (self.trade_edge, self.polar_edge, self.westerly_speed, T::one()),
+            Band::PolarEasterlies => (self.polar_edge, T::from(90.0), self.polar_speed, -T::one()),
+        };
+
+        // Strongest in the middle of the band and calm towards its edges
+        let within = ((latitude.abs() - low) / (high - low).max(T::default_epsilon()))
+            .max(T::zero())
+            .min(T::one());
+        let calm = T::from(CALM);
+        let speed = speed * (calm + (T::one() - calm) * (T::pi() * within).sin());
+
+        let turning = self.turning * T::pi() / T::from(180.0);
+        let direction = latitudes.east() * east * turning.cos() + latitudes.north() * east * hemisphere * turning.sin();
+        direction * speed
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Settings<T: RealField> {
-    simulation_duration: f32, // 1 unit = 1 day
+    max_steps: usize,
+    warm_start: bool,
     start_speed: T,
     start_from: Vector2<T>,
+    sea_level: T,
+    vertical_scale: T,
+    deflection: T,
+    drag: T,
+    acceleration: T,
+    recovery: T,
+    turbulence: T,
+    circulation: Option<Circulation<T>>,
 }
 impl<T: RealField + From<f32>> Settings<T> {
+    /// Largest number of propagation steps, stops early once the field settles.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Continue from the wind already on the regions instead of starting from the prevailing wind, so repeated calls
+    /// advance the same field. Regions without wind still start from the prevailing wind.
+    pub fn with_warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
+    pub fn with_start_speed(mut self, start_speed: T) -> Self {
+        self.start_speed = start_speed;
+        self
+    }
+
+    /// Side of the map the wind blows in from, the wind itself blows in the opposite direction.
+    pub fn with_start_from(mut self, start_from: Vector2<T>) -> Self {
+        self.start_from = start_from;
+        self
+    }
+
+    /// Takes the prevailing wind from the circulation bands instead of `start_from` and `start_speed`.
+    pub fn with_circulation(mut self, circulation: Circulation<T>) -> Self {
+        self.circulation = Some(circulation);
+        self
+    }
+
+    /// Regions below sea level are treated as flat water.
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Graph units per unit of elevation, see `slope::Settings::with_vertical_scale`.
+    pub fn with_vertical_scale(mut self, vertical_scale: T) -> Self {
+        self.vertical_scale = vertical_scale;
+        self
+    }
+
+    /// How strongly wind blowing uphill is turned along the slope instead of climbing it.
+    pub fn with_deflection(mut self, deflection: T) -> Self {
+        self.deflection = deflection;
+        self
+    }
+
+    /// How strongly wind blowing uphill is slowed, and downhill sped up.
+    pub fn with_slope_response(mut self, drag: T, acceleration: T) -> Self {
+        self.drag = drag;
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Fraction of the prevailing wind mixed back in every step, within `[0, 1]`.
+    pub fn with_recovery(mut self, recovery: T) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Largest random turn of the final wind direction in radians.
+    pub fn with_turbulence(mut self, turbulence: T) -> Self {
+        self.turbulence = turbulence;
+        self
+    }
+
+    pub fn circulation(&self) -> Option<&Circulation<T>> {
+        self.circulation.as_ref()
+    }
+
     pub fn default() -> Self {
         Self {
-            simulation_duration: 365.0.into(),
+            max_steps: 365,
+            warm_start: false,
             start_speed: 5.0.into(),
             start_from: Vector2::new(1.0.into(), 0.0.into()),
+            sea_level: 0.3.into(),
+            vertical_scale: 100.0.into(),
+            deflection: 1.0.into(),
+            drag: 1.0.into(),
+            acceleration: 0.5.into(),
+            recovery: 0.05.into(),
+            turbulence: 0.0.into(),
+            circulation: None,
         }
     }
 }
 
-pub fn visit<T, V, R, E>(
-    _region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
-    _settings: &Settings<T>,
-    _rng: &mut R,
-) -> Result<(), failure::Error>
+/// Turns `wind` by the terrain of a region and gives it the prevailing `speed` scaled by the slope. The speed is not
+/// taken from `wind`, so acceleration on a long descent does not compound from region to region. `uphill` is the
+/// dimensionless elevation gradient.
+fn terrain_response<T: RealField>(wind: Vector2<T>, speed: T, uphill: Vector2<T>, settings: &Settings<T>) -> Vector2<T> {
+    let steepness = uphill.norm();
+    let magnitude = wind.norm();
+    if magnitude <= T::zero() {
+        return wind;
+    }
+    if steepness <= T::zero() {
+        return wind / magnitude * speed;
+    }
+
+    let direction = uphill / steepness;
+    let climb = wind.dot(&direction);
+    let alignment = climb.abs() / magnitude;
+    if climb > T::zero() {
+        // Remove part of the uphill component, turning the wind along the contour lines
+        let turned = wind - direction * climb * (settings.deflection * steepness).min(T::one());
+        let turned = if turned.norm() > T::zero() {
+            turned.normalize()
+        } else {
+            wind / magnitude
+        };
+        turned * speed / (T::one() + settings.drag * steepness * alignment)
+    } else {
+        wind / magnitude * speed * (T::one() + settings.acceleration * steepness * alignment)
+    }
+}
+
+/// Neighbors whose wind blows towards each region of `winds`, a wind field indexed by region, weighted by the wind
+/// speed along the direction to the region. The wind stage mixes the wind of these neighbors and stages carrying heat
+/// or moisture with the wind mix in their values.
+pub fn upwind<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, winds: &[Vector2<T>]) -> Vec<SmallVec<[(RegionNodeIdx, T); 8]>>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    region_graph
+        .node_indices()
+        .map(|region| {
+            let position = region_graph[region].pos;
+            region_graph
+                .neighbors(region)
+                .filter_map(|neighbor| {
+                    let offset = position - region_graph[neighbor].pos;
+                    let offset = Vector2::new(T::from(offset.x), T::from(offset.y));
+                    let length = offset.norm();
+                    if length <= T::zero() {
+                        return None;
+                    }
+                    let weight = winds[neighbor.index()].dot(&offset) / length;
+                    if weight > T::zero() {
+                        Some((neighbor, weight))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// `upwind` of the wind currently on the regions.
+pub fn upwind_of_regions<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>) -> Vec<SmallVec<[(RegionNodeIdx, T); 8]>>
 where
-    T: RealField,
-    V: Default + HasElevation<T> + HasWind<T> + HasTemperature<T>,
+    T: RealField + From<f32>,
+    V: HasWind<T>,
+    E: EdgeType,
+{
+    let winds = region_graph
+        .node_indices()
+        .map(|region| region_graph[region].value.wind_vector())
+        .collect::<Vec<_>>();
+    upwind(region_graph, &winds)
+}
+
+pub fn visit<T, V, R, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>, rng: &mut R) -> Result<(), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T> + HasWind<T>,
     R: rand::Rng + ?Sized,
     E: EdgeType,
     rand::distributions::Standard: rand::distributions::Distribution<T>,
 {
-    // Just place wind direction on all elements for testing drawing
+    if settings.circulation.is_none() && settings.start_from.norm() <= T::zero() {
+        return Err(failure::format_err!("Wind start direction must not be zero"));
+    }
+    if settings.recovery < T::zero() || settings.recovery > T::one() {
+        return Err(failure::format_err!("Wind recovery must be within [0, 1]"));
+    }
+
+    let base = match &settings.circulation {
+        Some(circulation) => {
+            if circulation.trade_edge <= T::zero() || circulation.trade_edge >= circulation.polar_edge || circulation.polar_edge >= T::from(90.0) {
+                return Err(failure::format_err!(
+                    "Circulation band edges must satisfy 0 < trade edge < polar edge < 90"
+                ));
+            }
+            let latitudes = circulation.latitude.visit(region_graph);
+            region_graph
+                .node_indices()
+                .map(|region| circulation.wind(latitudes.latitude(region), &latitudes))
+                .collect::<Vec<_>>()
+        }
+        None => vec![-settings.start_from.normalize() * settings.start_speed; region_graph.node_count()],
+    };
+
+    let slopes = slope::visit(region_graph, &slope::Settings::default().with_vertical_scale(settings.vertical_scale))?;
+    let uphill = region_graph
+        .node_indices()
+        .map(|region| {
+            if region_graph[region].value.elevation() < settings.sea_level {
+                Vector2::zeros()
+            } else {
+                slopes.gradient(region) * settings.vertical_scale
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut winds = region_graph
+        .node_indices()
+        .map(|region| {
+            let current = region_graph[region].value.wind_vector();
+            if settings.warm_start && current.norm() > T::zero() {
+                current
+            } else {
+                terrain_response(base[region.index()], base[region.index()].norm(), uphill[region.index()], settings)
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut next = winds.clone();
+    // Settled relative to the fastest prevailing wind, whether it comes from `start_speed` or the circulation bands
+    let settled = base.iter().fold(T::zero(), |fastest, wind| fastest.max(wind.norm())) * T::from(1.0e-4);
+
+    // Air arrives along the prevailing wind, so which neighbors are upwind does not change while the terrain turns the
+    // wind, and regions cannot keep swapping which of them feeds the other
+    let upwind = upwind(region_graph, &base);
+
+    for _ in 0..settings.max_steps {
+        let mut change = T::zero();
+        for region in region_graph.node_indices() {
+            // Wind arriving from the neighbors upwind of the prevailing wind
+            let (sum, total) = upwind[region.index()]
+                .iter()
+                .fold((Vector2::zeros(), T::zero()), |(sum, total), (neighbor, weight)| {
+                    (sum + winds[neighbor.index()] * *weight, total + *weight)
+                });
+
+            let arriving = if total > T::zero() { sum / total } else { base[region.index()] };
+            let mixed = arriving * (T::one() - settings.recovery) + base[region.index()] * settings.recovery;
+            let wind = terrain_response(mixed, base[region.index()].norm(), uphill[region.index()], settings);
+
+            change = change.max((wind - winds[region.index()]).norm());
+            next[region.index()] = wind;
+        }
+
+        std::mem::swap(&mut winds, &mut next);
+        if change < settled {
+            break;
+        }
+    }
+
+    for (region, wind) in region_graph.node_weights_mut().zip(winds) {
+        let wind = if settings.turbulence > T::zero() {
+            let angle = (rng.gen::<T>() * T::from(2.0) - T::one()) * settings.turbulence;
+            let (sin, cos) = (angle.sin(), angle.cos());
+            Vector2::new(wind.x * cos - wind.y * sin, wind.x * sin + wind.y * cos)
+        } else {
+            wind
+        };
+        region.value.set_wind_vector(wind);
+    }
 
     Ok(())
 }
@@ -40,26 +409,18 @@ where
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::dual_graph::RegionGraph;
     use crate::peak_automata;
-    use crate::{
-        dual_graph::{gen_dual_graph, RegionGraph},
-        HasValue,
-    };
     use nalgebra::Point2;
-    use petgraph::visit::{IntoNodeReferences};
-    use rand::SeedableRng;
-    use rand_xorshift::XorShiftRng;
 
     struct TestInner {
         elevation: f32,
-        temperature: f32,
         wind: Vector2<f32>,
     }
     impl Default for TestInner {
         fn default() -> Self {
             Self {
                 elevation: 0.0,
-                temperature: 21.0,
                 wind: Vector2::new(0.0, 0.0),
             }
         }
@@ -73,15 +434,6 @@ pub mod tests {
             self.elevation = height;
         }
     }
-    impl HasTemperature<f32> for TestInner {
-        fn temperature(&self) -> f32 {
-            self.temperature
-        }
-        fn set_temperature(&mut self, temp: f32) {
-            self.temperature = temp;
-        }
-    }
-
     impl HasWind<f32> for TestInner {
         fn wind_vector(&self) -> Vector2<f32> {
             self.wind
@@ -91,83 +443,101 @@ pub mod tests {
         }
     }
 
-    fn apply_peak_automata<R>(
-        dims: &Vector2<f32>,
-        region_graph: &mut RegionGraph<TestInner>,
-        rng: &mut R,
-    ) where
-        R: rand::Rng,
-    {
-        use crate::peak_automata::{node_for_coordinate, PeakNode, Settings};
-
-        let center = Point2::from(dims / 2.0);
-        let soft_points = (0..20)
-            .map(|_| {
-                let height = rng.gen_range(0.3, 0.8);
-                let x = rng.gen_range(-500.0, 500.0);
-                let y = rng.gen_range(-500.0, 500.0);
-                PeakNode {
-                    node: node_for_coordinate(
-                        &region_graph,
-                        Point2::new(center.x + x, center.y + y),
-                    )
-                    .expect("wut"),
-                    elevation: height,
-                }
-            })
-            .collect::<Vec<_>>();
+    #[test]
+    fn wind_around_hill() {
+        let (mut region_graph, _, mut rng) = peak_automata::tests::test_graph::<TestInner>();
 
-        let settings = Settings::<f32>::default().with_peak_nodes(soft_points);
+        // A round hill in the middle of flat land
+        let center = Point2::new(512.0, 512.0);
+        for region in region_graph.node_weights_mut() {
+            let distance = nalgebra::distance(&region.pos, &center);
+            region.value.elevation = 0.4 + 0.4 * (-(distance * distance) / (2.0 * 120.0 * 120.0)).exp();
+        }
 
-        peak_automata::visit(region_graph, &settings, rng).unwrap();
+        let settings = Settings::<f32>::default();
+        visit(&mut region_graph, &settings, &mut rng).unwrap();
+
+        let (mut turned, mut flank, mut speed) = (0.0, 0, 0.0);
+        for region in region_graph.node_weights_mut() {
+            let wind = region.value.wind_vector();
+            let offset = region.pos - center;
+            if offset.x > 950.0 - 512.0 {
+                // Upwind of the hill the wind is untouched
+                assert!((wind - Vector2::new(-5.0, 0.0)).norm() < 0.05);
+            }
+            if offset.x > 60.0 && offset.x < 200.0 && offset.y.abs() > 40.0 && offset.y.abs() < 200.0 {
+                // The windward flank turns the wind away from the summit and slows it
+                turned += wind.y * offset.y.signum();
+                speed += wind.norm();
+                flank += 1;
+            }
+            assert!(wind.x < 0.0);
+        }
+        assert!(flank > 0 && turned > 0.0);
+        assert!(speed / (flank as f32) < 5.0);
     }
 
     #[test]
-    fn wind_test() {
-        use petgraph::visit::NodeRef;
+    fn wind_circulation() {
+        let (mut region_graph, _, mut rng) = peak_automata::tests::test_graph::<TestInner>();
 
-        let dims = Vector2::new(1024.0, 1024.0);
-        let mut imgbuf =
-            image::ImageBuffer::from_pixel(dims.x as u32, dims.y as u32, image::Rgb([0, 0, 0]));
+        let circulation = Circulation::<f32>::default();
+        let settings = Settings::<f32>::default().with_circulation(circulation.clone());
+        visit(&mut region_graph, &settings, &mut rng).unwrap();
+
+        // North is up, so east is positive x and north is negative y
+        let latitudes = circulation.latitude().visit(&region_graph);
+        for region in region_graph.node_indices() {
+            let latitude = latitudes.latitude(region);
+            let wind = region_graph[region].value.wind_vector();
+            let (east, north) = (wind.x, -wind.y);
+            let middle = |low: f32, high: f32| latitude.abs() > low + 5.0 && latitude.abs() < high - 5.0;
+            if middle(0.0, 30.0) {
+                assert_eq!(circulation.band(latitude), Band::Trades);
+                assert!(east < 0.0 && north * latitude < 0.0);
+            } else if middle(30.0, 60.0) {
+                assert_eq!(circulation.band(latitude), Band::Westerlies);
+                assert!(east > 0.0 && north * latitude > 0.0);
+            } else if middle(60.0, 90.0) {
+                assert_eq!(circulation.band(latitude), Band::PolarEasterlies);
+                assert!(east < 0.0 && north * latitude < 0.0);
+            }
+        }
 
-        let mut rng = XorShiftRng::from_seed([
-            122, 154, 21, 182, 159, 131, 187, 243, 134, 230, 110, 10, 31, 174, 6, 4,
-        ]);
+        let inverted = Settings::<f32>::default().with_circulation(Circulation::default().with_band_edges(60.0, 30.0));
+        assert!(visit(&mut region_graph, &inverted, &mut rng).is_err());
+    }
 
-        let (mut region_graph, _border_graph) =
-            gen_dual_graph::<TestInner, (), XorShiftRng>(dims, 8000, 2, &mut rng);
+    #[test]
+    fn wind_test() {
+        use crate::wind_map::{self, Style};
 
-        apply_peak_automata(&dims, &mut region_graph, &mut rng);
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::island::<TestInner>();
 
         let settings = Settings::<f32>::default();
         visit(&mut region_graph, &settings, &mut rng).unwrap();
+        assert!(region_graph.node_weights_mut().all(|region| region.value.wind_vector().norm() > 0.0));
 
-        for node in region_graph.node_references() {
-            let region = node.weight();
-            let value = region.value();
-
-            if value.wind_vector().magnitude() > 0.0 {
-                let start = &region.pos;
-                let end = start + (value.wind_vector().normalize() * 10.0);
-
-                imageproc::drawing::draw_antialiased_line_segment_mut(
-                    &mut imgbuf,
-                    (start.x as i32, start.y as i32),
-                    (end.x as i32, end.y as i32),
-                    image::Rgb([0, 222, 222]),
-                    imageproc::pixelops::interpolate,
-                );
-
-                // Draw a circle at the source direction
-                imageproc::drawing::draw_filled_circle_mut(
-                    &mut imgbuf,
-                    (start.x as i32, start.y as i32),
-                    2,
-                    image::Rgb([0, 222, 222]),
-                );
-            }
-        }
+        // A warm start continues from the settled field instead of starting over from the prevailing wind
+        let settled = region_graph
+            .node_weights_mut()
+            .map(|region| region.value.wind_vector())
+            .collect::<Vec<_>>();
+        visit(&mut region_graph, &settings.clone().with_warm_start(true).with_max_steps(1), &mut rng).unwrap();
+        let drift = |region_graph: &mut RegionGraph<TestInner>| {
+            region_graph
+                .node_weights_mut()
+                .zip(&settled)
+                .fold(0.0_f32, |drift, (region, wind)| drift.max((region.value.wind_vector() - wind).norm()))
+        };
+        assert!(drift(&mut region_graph) < 0.01);
+        visit(&mut region_graph, &settings.clone().with_max_steps(1), &mut rng).unwrap();
+        assert!(drift(&mut region_graph) > 0.01);
 
-        imgbuf.save("output/simple_wind.png").unwrap();
+        for (style, name) in &[(Style::Streamlines, "simple_wind"), (Style::Arrows, "simple_wind_arrows")] {
+            let settings = wind_map::Settings::<f32>::default().with_style(*style);
+            let imgbuf = wind_map::to_image(&region_graph, &border_graph, &settings).unwrap();
+            imgbuf.save(format!("output/{}.png", name)).unwrap();
+        }
     }
 }
@@ -0,0 +1,248 @@
+//! Slope
+//! Estimates terrain derivatives for every region. The elevation gradient is fitted by least squares to the position
+//! and elevation differences towards the neighbors of a region, and gives the slope angle and the aspect, the direction
+//! the terrain faces downhill. Curvature is estimated with the same distance weighted Laplacian used for hillslope
+//! diffusion, positive in valleys and negative on ridges.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    HasElevation,
+};
+use nalgebra::{RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+#[derive(Clone, Debug)]
+pub struct Slopes<T: RealField> {
+    gradients: Vec<Vector2<T>>,
+    angles: Vec<T>,
+    aspects: Vec<T>,
+    curvatures: Vec<T>,
+}
+impl<T: RealField> Slopes<T> {
+    /// Elevation change per graph unit along each axis.
+    pub fn gradient(&self, region: RegionNodeIdx) -> Vector2<T> {
+        self.gradients[region.index()]
+    }
+
+    /// Slope angle in radians, zero for flat terrain.
+    pub fn slope(&self, region: RegionNodeIdx) -> T {
+        self.angles[region.index()]
+    }
+
+    /// Downhill direction in radians from the positive x axis, within `[-pi, pi]`. Zero for flat terrain.
+    pub fn aspect(&self, region: RegionNodeIdx) -> T {
+        self.aspects[region.index()]
+    }
+
+    /// Laplacian of the elevation, positive where the terrain curves upwards.
+    pub fn curvature(&self, region: RegionNodeIdx) -> T {
+        self.curvatures[region.index()]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    vertical_scale: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Graph units per unit of elevation, used to turn the gradient into a slope angle.
+    pub fn with_vertical_scale(mut self, vertical_scale: T) -> Self {
+        self.vertical_scale = vertical_scale;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            vertical_scale: 100.0.into(),
+        }
+    }
+}
+
+/// Least squares gradient of `values` around a region, fitted to the position and value differences towards the
+/// neighbors accepted by `include`. Zero when the neighbors do not span a plane.
+pub(crate) fn fit_gradient<T, V, E, F>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    region: RegionNodeIdx,
+    values: &[T],
+    include: F,
+) -> Vector2<T>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+    F: Fn(RegionNodeIdx) -> bool,
+{
+    let position = region_graph[region].pos;
+    let value = values[region.index()];
+
+    // Normal equations of the plane fit
+    let (mut xx, mut xy, mut yy, mut xz, mut yz) = (T::zero(), T::zero(), T::zero(), T::zero(), T::zero());
+    for neighbor in region_graph.neighbors(region).filter(|neighbor| include(*neighbor)) {
+        let offset = region_graph[neighbor].pos - position;
+        let (dx, dy) = (T::from(offset.x), T::from(offset.y));
+        let dz = values[neighbor.index()] - value;
+
+        xx += dx * dx;
+        xy += dx * dy;
+        yy += dy * dy;
+        xz += dx * dz;
+        yz += dy * dz;
+    }
+
+    let determinant = xx * yy - xy * xy;
+    if determinant.abs() > T::default_epsilon() {
+        Vector2::new((yy * xz - xy * yz) / determinant, (xx * yz - xy * xz) / determinant)
+    } else {
+        Vector2::zeros()
+    }
+}
+
+/// Weights of the graph Laplacian towards the neighbors of every region, inverse to the squared distance and scaled so
+/// a regular quad grid gives the usual stencil. Neighbors at the same position are left out.
+pub(crate) fn laplacian_weights<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>) -> Vec<Vec<(RegionNodeIdx, T)>>
+where
+    T: RealField + From<f32>,
+    E: EdgeType,
+{
+    region_graph
+        .node_indices()
+        .map(|region| {
+            let position = region_graph[region].pos;
+            #[allow(clippy::cast_precision_loss)]
+            let scale = T::from(4.0 / region_graph.neighbors(region).count().max(1) as f32);
+
+            region_graph
+                .neighbors(region)
+                .filter_map(|neighbor| {
+                    let squared = T::from(nalgebra::distance_squared(&position, &region_graph[neighbor].pos));
+                    if squared > T::zero() {
+                        Some((neighbor, scale / squared))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn visit<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Slopes<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.vertical_scale <= T::zero() {
+        return Err(failure::format_err!("Slope vertical scale must be positive"));
+    }
+
+    let count = region_graph.node_count();
+    let mut slopes = Slopes {
+        gradients: Vec::with_capacity(count),
+        angles: Vec::with_capacity(count),
+        aspects: Vec::with_capacity(count),
+        curvatures: Vec::with_capacity(count),
+    };
+
+    let elevations = region_graph
+        .node_indices()
+        .map(|region| region_graph[region].value.elevation())
+        .collect::<Vec<_>>();
+    for (region, weights) in region_graph.node_indices().zip(laplacian_weights(region_graph)) {
+        let gradient = fit_gradient(region_graph, region, &elevations, |_| true);
+        let laplacian = weights.iter().fold(T::zero(), |acc, (neighbor, weight)| {
+            acc + (elevations[neighbor.index()] - elevations[region.index()]) * *weight
+        });
+        let steepness = gradient.norm();
+
+        slopes.gradients.push(gradient);
+        slopes.angles.push((steepness * settings.vertical_scale).atan());
+        slopes.aspects.push(if steepness > T::zero() {
+            (-gradient.y).atan2(-gradient.x)
+        } else {
+            T::zero()
+        });
+        slopes.curvatures.push(laplacian);
+    }
+
+    Ok(slopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dual_graph::is_boundary_region;
+    use crate::peak_automata;
+    use nalgebra::Point2;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+        shade: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn slope_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::test_graph::<TestInner>();
+
+        // A tilted plane descending towards positive x has an exact gradient
+        for region in region_graph.node_weights_mut() {
+            let elevation = 1.0 - region.pos.x / 1024.0 + region.pos.y / 2048.0;
+            region.value.set_elevation(elevation);
+        }
+        let plane = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+        for region in region_graph.node_indices() {
+            if is_boundary_region(&region_graph, region) {
+                continue;
+            }
+            let gradient = plane.gradient(region);
+            assert!((gradient.x + 1.0 / 1024.0).abs() < 1.0e-5 && (gradient.y - 1.0 / 2048.0).abs() < 1.0e-5);
+            assert!(plane.aspect(region).abs() < 0.5);
+        }
+
+        // A bowl curves upwards, away from the one sided neighborhoods on the map edge
+        for region in region_graph.node_weights_mut() {
+            let offset = region.pos - Point2::new(512.0, 512.0);
+            region.value.set_elevation(offset.norm_squared() / (512.0 * 512.0));
+        }
+        let bowl = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+        let curvature = region_graph
+            .node_indices()
+            .filter(|region| !is_boundary_region(&region_graph, *region))
+            .map(|region| bowl.curvature(region))
+            .sum::<f32>();
+        assert!(curvature > 0.0);
+
+        for region in region_graph.node_weights_mut() {
+            region.value.set_elevation(0.0);
+        }
+        peak_automata::tests::raise_island(&mut region_graph, &mut rng);
+
+        let slopes = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+        let light = Vector2::new(-1.0, -1.0).normalize();
+        for region in region_graph.node_indices() {
+            let slope = slopes.slope(region);
+            assert!((0.0..std::f32::consts::FRAC_PI_2).contains(&slope));
+            // Hillshade lit from the top left
+            let aspect = slopes.aspect(region);
+            let facing = Vector2::new(aspect.cos(), aspect.sin()).dot(&light);
+            region_graph[region].value.shade = 0.5 + 0.5 * facing * slope.sin();
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let shade = (255.0 * region.value.shade.min(1.0).max(0.0)) as u8;
+
+            image::Rgb([shade, shade, shade])
+        });
+
+        imgbuf.save("output/slope.png").unwrap();
+    }
+}
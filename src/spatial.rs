@@ -0,0 +1,141 @@
+//! Spatial
+//! Uniform grid index over region positions, answering which region is closest to a point and which regions lie
+//! within a radius without scanning the whole graph. The closest region is the one whose Voronoi polygon contains the
+//! point, so this is also how rasterizers and brushes find the region under a pixel.
+//!
+use crate::dual_graph::{RegionEdge, RegionNode, RegionNodeIdx};
+use nalgebra::Point2;
+use petgraph::{EdgeType, Graph};
+
+#[derive(Clone, Debug)]
+pub struct SpatialIndex {
+    origin: Point2<f32>,
+    cell: f32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<RegionNodeIdx>>,
+    positions: Vec<Point2<f32>>,
+}
+impl SpatialIndex {
+    /// Builds the index with roughly one region per cell.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+    pub fn new<V, E: EdgeType>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>) -> Self {
+        let positions = region_graph.node_indices().map(|region| region_graph[region].pos).collect::<Vec<_>>();
+        let (low, high) = positions.iter().fold(
+            (Point2::new(std::f32::MAX, std::f32::MAX), Point2::new(std::f32::MIN, std::f32::MIN)),
+            |(low, high), pos| {
+                (
+                    Point2::new(low.x.min(pos.x), low.y.min(pos.y)),
+                    Point2::new(high.x.max(pos.x), high.y.max(pos.y)),
+                )
+            },
+        );
+
+        let (origin, extent) = if positions.is_empty() {
+            (Point2::origin(), 1.0)
+        } else {
+            (low, (high.x - low.x).max(high.y - low.y).max(std::f32::EPSILON))
+        };
+        let cell = (extent * extent / positions.len().max(1) as f32).sqrt().max(std::f32::EPSILON);
+        let columns = ((high.x - origin.x).max(0.0) / cell) as usize + 1;
+        let rows = ((high.y - origin.y).max(0.0) / cell) as usize + 1;
+
+        let mut index = Self {
+            origin,
+            cell,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+            positions,
+        };
+        for (i, pos) in index.positions.iter().enumerate() {
+            let (column, row) = index.cell_of(*pos);
+            index.cells[row * index.columns + column].push(RegionNodeIdx::new(i));
+        }
+        index
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn cell_of(&self, point: Point2<f32>) -> (usize, usize) {
+        let column = ((point.x - self.origin.x) / self.cell).max(0.0) as usize;
+        let row = ((point.y - self.origin.y) / self.cell).max(0.0) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    /// Region closest to the point, `None` for an empty graph.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn nearest(&self, point: Point2<f32>) -> Option<RegionNodeIdx> {
+        let (column, row) = self.cell_of(point);
+        let mut best: Option<(RegionNodeIdx, f32)> = None;
+
+        // Search rings of cells outwards until nothing in the next ring can be closer than the best found
+        for ring in 0..=self.columns.max(self.rows) {
+            let (left, right) = (column.saturating_sub(ring), (column + ring).min(self.columns - 1));
+            let (top, bottom) = (row.saturating_sub(ring), (row + ring).min(self.rows - 1));
+            for y in top..=bottom {
+                for x in left..=right {
+                    if x + ring != column && x != column + ring && y + ring != row && y != row + ring {
+                        continue;
+                    }
+                    for region in &self.cells[y * self.columns + x] {
+                        let distance = nalgebra::distance_squared(&self.positions[region.index()], &point);
+                        if best.map_or(true, |(_, closest)| distance < closest) {
+                            best = Some((*region, distance));
+                        }
+                    }
+                }
+            }
+
+            let reach = ring as f32 * self.cell;
+            if best.map_or(false, |(_, closest)| closest <= reach * reach) {
+                break;
+            }
+        }
+
+        best.map(|(region, _)| region)
+    }
+
+    /// Regions within `radius` of the point.
+    pub fn within(&self, point: Point2<f32>, radius: f32) -> Vec<RegionNodeIdx> {
+        let (left, top) = self.cell_of(Point2::new(point.x - radius, point.y - radius));
+        let (right, bottom) = self.cell_of(Point2::new(point.x + radius, point.y + radius));
+        let squared = radius * radius;
+
+        let mut found = Vec::new();
+        for y in top..=bottom {
+            for x in left..=right {
+                found.extend(
+                    self.cells[y * self.columns + x]
+                        .iter()
+                        .filter(|region| nalgebra::distance_squared(&self.positions[region.index()], &point) <= squared),
+                );
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate};
+    use rand::Rng;
+
+    #[test]
+    pub fn spatial_index() {
+        let (region_graph, _, mut rng) = peak_automata::tests::test_graph::<()>();
+        let index = SpatialIndex::new(&region_graph);
+
+        for _ in 0..200 {
+            let point = Point2::new(rng.gen_range(-50.0, 1074.0), rng.gen_range(-50.0, 1074.0));
+            let expected = node_for_coordinate(&region_graph, point).unwrap();
+            let found = index.nearest(point).unwrap();
+            let distance = |region: RegionNodeIdx| nalgebra::distance(&region_graph[region].pos, &point);
+            assert!((distance(found) - distance(expected)).abs() < 1.0e-3);
+
+            let within = index.within(point, 40.0);
+            let expected = region_graph.node_indices().filter(|region| distance(*region) <= 40.0).count();
+            assert_eq!(within.len(), expected);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Tectonics
+//! Partitions the graph into plates with a randomized flood fill, and gives every plate a motion vector and a crust
+//! type. Edges between plates are classified as convergent, divergent or transform from the relative plate motion, and
+//! elevation is raised or lowered outwards from those boundaries: mountains where continents collide, trenches where
+//! oceanic crust subducts and rifts where plates pull apart.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionEdgeIdx, RegionNode, RegionNodeIdx},
+    Blend, HasElevation,
+};
+use nalgebra::{RealField, Vector2};
+use petgraph::{visit::EdgeRef, EdgeType, Graph};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum PlateKind {
+    Oceanic,
+    Continental,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Boundary {
+    /// Plates move towards each other.
+    Convergent,
+    /// Plates move away from each other.
+    Divergent,
+    /// Plates slide past each other.
+    Transform,
+}
+
+#[derive(Clone, Debug)]
+pub struct Plate<T: RealField> {
+    pub seed: RegionNodeIdx,
+    pub kind: PlateKind,
+    pub motion: Vector2<T>,
+}
+
+/// Plate assignment of every region and the classification of every edge between two plates.
+#[derive(Clone, Debug)]
+pub struct Plates<T: RealField> {
+    pub plates: Vec<Plate<T>>,
+    plate_of: Vec<usize>,
+    boundaries: HashMap<RegionEdgeIdx, Boundary>,
+}
+impl<T: RealField> Plates<T> {
+    /// Index into `plates` of the plate owning the region.
+    pub fn plate(&self, region: RegionNodeIdx) -> usize {
+        self.plate_of[region.index()]
+    }
+
+    pub fn kind(&self, region: RegionNodeIdx) -> PlateKind {
+        self.plates[self.plate(region)].kind
+    }
+
+    /// Boundary type of an edge, `None` if both regions belong to the same plate.
+    pub fn boundary(&self, edge: RegionEdgeIdx) -> Option<Boundary> {
+        self.boundaries.get(&edge).copied()
+    }
+
+    pub fn boundaries(&self) -> impl Iterator<Item = (RegionEdgeIdx, Boundary)> + '_ {
+        self.boundaries.iter().map(|(edge, boundary)| (*edge, *boundary))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    num_plates: usize,
+    continental_fraction: T,
+    speed: T,
+    transform_ratio: T,
+    oceanic_elevation: T,
+    continental_elevation: T,
+    mountain: T,
+    trench: T,
+    rift: T,
+    falloff: usize,
+    blend: Blend,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_num_plates(mut self, num_plates: usize) -> Self {
+        self.num_plates = num_plates;
+        self
+    }
+
+    /// Chance for every plate to be continental rather than oceanic.
+    pub fn with_continental_fraction(mut self, continental_fraction: T) -> Self {
+        self.continental_fraction = continental_fraction;
+        self
+    }
+
+    /// Maximum plate speed, motion vectors are drawn uniformly up to this magnitude.
+    pub fn with_speed(mut self, speed: T) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Boundaries whose normal motion is below this fraction of the relative motion are transform boundaries.
+    pub fn with_transform_ratio(mut self, transform_ratio: T) -> Self {
+        self.transform_ratio = transform_ratio;
+        self
+    }
+
+    pub fn with_base_elevation(mut self, oceanic: T, continental: T) -> Self {
+        self.oceanic_elevation = oceanic;
+        self.continental_elevation = continental;
+        self
+    }
+
+    pub fn with_mountain(mut self, mountain: T) -> Self {
+        self.mountain = mountain;
+        self
+    }
+
+    pub fn with_trench(mut self, trench: T) -> Self {
+        self.trench = trench;
+        self
+    }
+
+    pub fn with_rift(mut self, rift: T) -> Self {
+        self.rift = rift;
+        self
+    }
+
+    /// Number of regions over which a boundary feature fades out into the plate interior.
+    pub fn with_falloff(mut self, falloff: usize) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: Blend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            num_plates: 12,
+            continental_fraction: 0.4.into(),
+            speed: 1.0.into(),
+            transform_ratio: 0.3.into(),
+            oceanic_elevation: 0.1.into(),
+            continental_elevation: 0.45.into(),
+            mountain: 0.45.into(),
+            trench: 0.1.into(),
+            rift: 0.15.into(),
+            falloff: 8,
+            blend: Blend::Replace,
+        }
+    }
+}
+
+fn flood_fill<V, R, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, seeds: &[RegionNodeIdx], rng: &mut R) -> Result<Vec<usize>, failure::Error>
+where
+    R: rand::Rng + ?Sized,
+    E: EdgeType,
+{
+    let mut plate_of = vec![None; region_graph.node_count()];
+    let mut frontier = Vec::with_capacity(region_graph.node_count());
+
+    for (plate, seed) in seeds.iter().enumerate() {
+        plate_of[seed.index()] = Some(plate);
+        frontier.push(*seed);
+    }
+
+    // Growing from a random frontier region instead of in BFS order gives irregular plate outlines
+    while !frontier.is_empty() {
+        let current = frontier.swap_remove(rng.gen_range(0, frontier.len()));
+        let plate = plate_of[current.index()];
+
+        for neighbor in region_graph.neighbors(current) {
+            if plate_of[neighbor.index()].is_none() {
+                plate_of[neighbor.index()] = plate;
+                frontier.push(neighbor);
+            }
+        }
+    }
+
+    plate_of
+        .into_iter()
+        .enumerate()
+        .map(|(i, plate)| plate.ok_or_else(|| failure::format_err!("Region {} is not connected to any plate", i)))
+        .collect()
+}
+
+fn classify<T: RealField>(relative: Vector2<T>, normal: Vector2<T>, transform_ratio: T) -> Boundary {
+    let closing = -relative.dot(&normal);
+
+    if closing.abs() <= relative.norm() * transform_ratio {
+        Boundary::Transform
+    } else if closing > T::zero() {
+        Boundary::Convergent
+    } else {
+        Boundary::Divergent
+    }
+}
+
+/// Elevation change on the region of plate `this` at a boundary with plate `other`.
+fn boundary_effect<T: RealField + From<f32>>(
+    settings: &Settings<T>,
+    boundary: Boundary,
+    this: PlateKind,
+    other: PlateKind,
+    this_index: usize,
+    other_index: usize,
+) -> T {
+    let half: T = 0.5.into();
+
+    match (boundary, this, other) {
+        (Boundary::Convergent, PlateKind::Continental, _) => settings.mountain,
+        (Boundary::Convergent, PlateKind::Oceanic, PlateKind::Continental) => -settings.trench,
+        // Between two oceanic plates one side subducts into a trench and the other rises as an island arc
+        (Boundary::Convergent, PlateKind::Oceanic, PlateKind::Oceanic) => {
+            if this_index < other_index {
+                -settings.trench
+            } else {
+                settings.mountain * half
+            }
+        }
+        (Boundary::Divergent, _, _) => -settings.rift,
+        (Boundary::Transform, _, _) => T::zero(),
+    }
+}
+
+pub fn visit<T, V, R, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    rng: &mut R,
+) -> Result<Plates<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    R: rand::Rng + ?Sized,
+    E: EdgeType,
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    use rand::seq::index::sample;
+
+    if settings.num_plates == 0 || settings.num_plates > region_graph.node_count() {
+        return Err(failure::format_err!(
+            "Cannot split {} regions into {} plates",
+            region_graph.node_count(),
+            settings.num_plates
+        ));
+    }
+
+    let seeds = sample(rng, region_graph.node_count(), settings.num_plates)
+        .into_iter()
+        .map(RegionNodeIdx::new)
+        .collect::<Vec<_>>();
+    let plate_of = flood_fill(region_graph, &seeds, rng)?;
+
+    let plates = seeds
+        .iter()
+        .map(|seed| {
+            let kind = if rng.gen::<T>() < settings.continental_fraction {
+                PlateKind::Continental
+            } else {
+                PlateKind::Oceanic
+            };
+            let angle = rng.gen::<T>() * T::two_pi();
+            let speed = rng.gen::<T>() * settings.speed;
+
+            Plate {
+                seed: *seed,
+                kind,
+                motion: Vector2::new(angle.cos(), angle.sin()) * speed,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Classify every edge crossing plates and seed the strongest boundary feature on either side
+    let mut boundaries = HashMap::new();
+    let mut features: Vec<Option<T>> = vec![None; region_graph.node_count()];
+    for edge in region_graph.edge_references() {
+        let (a, b) = (edge.source(), edge.target());
+        let (plate_a, plate_b) = (plate_of[a.index()], plate_of[b.index()]);
+        if plate_a == plate_b {
+            continue;
+        }
+
+        let offset = region_graph[b].pos - region_graph[a].pos;
+        let normal = Vector2::new(T::from(offset.x), T::from(offset.y)).normalize();
+        let relative = plates[plate_b].motion - plates[plate_a].motion;
+        let boundary = classify(relative, normal, settings.transform_ratio);
+        boundaries.insert(edge.id(), boundary);
+
+        for &(region, this, other) in &[(a, plate_a, plate_b), (b, plate_b, plate_a)] {
+            let effect = boundary_effect(settings, boundary, plates[this].kind, plates[other].kind, this, other);
+            let feature = &mut features[region.index()];
+            if feature.map_or(true, |current| effect.abs() > current.abs()) {
+                *feature = Some(effect);
+            }
+        }
+    }
+
+    // Spread the features into the plate interiors, never crossing onto another plate
+    let mut hops = vec![None; region_graph.node_count()];
+    let mut queue = VecDeque::new();
+    for (i, feature) in features.iter().enumerate() {
+        if feature.is_some() {
+            hops[i] = Some(0);
+            queue.push_back(RegionNodeIdx::new(i));
+        }
+    }
+    while let Some(current) = queue.pop_front() {
+        let distance = hops[current.index()].unwrap_or(0);
+        if distance >= settings.falloff {
+            continue;
+        }
+
+        for neighbor in region_graph.neighbors(current) {
+            if hops[neighbor.index()].is_none() && plate_of[neighbor.index()] == plate_of[current.index()] {
+                hops[neighbor.index()] = Some(distance + 1);
+                features[neighbor.index()] = features[current.index()];
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let falloff = T::from(settings.falloff as f32 + 1.0);
+    for (i, region) in region_graph.node_weights_mut().enumerate() {
+        let plate = &plates[plate_of[i]];
+        let base = match plate.kind {
+            PlateKind::Oceanic => settings.oceanic_elevation,
+            PlateKind::Continental => settings.continental_elevation,
+        };
+        let feature = match (features[i], hops[i]) {
+            #[allow(clippy::cast_precision_loss)]
+            (Some(feature), Some(distance)) => {
+                let fade = T::one() - T::from(distance as f32) / falloff;
+                feature * fade * fade
+            }
+            _ => T::zero(),
+        };
+
+        let elevation = settings.blend.apply(region.value.elevation(), base + feature);
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(Plates {
+        plates,
+        plate_of,
+        boundaries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn tectonics_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::test_graph::<TestInner>();
+
+        let settings = Settings::<f32>::default();
+        let plates = visit(&mut region_graph, &settings, &mut rng).unwrap();
+
+        assert_eq!(plates.plates.len(), 12);
+        for edge in region_graph.edge_references() {
+            let crosses = plates.plate(edge.source()) != plates.plate(edge.target());
+            assert_eq!(crosses, plates.boundary(edge.id()).is_some());
+        }
+        assert!(plates.boundaries().any(|(_, boundary)| boundary == Boundary::Convergent));
+        assert!(plates.boundaries().any(|(_, boundary)| boundary == Boundary::Divergent));
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+        for (edge, boundary) in plates.boundaries() {
+            let color = match boundary {
+                Boundary::Convergent => image::Rgb([222, 0, 0]),
+                Boundary::Divergent => image::Rgb([0, 0, 222]),
+                Boundary::Transform => image::Rgb([0, 222, 0]),
+            };
+            let borders = &region_graph[edge].borders;
+            let (from, to) = (border_graph[borders[0]].pos, border_graph[borders[1]].pos);
+            imageproc::drawing::draw_antialiased_line_segment_mut(
+                &mut imgbuf,
+                (from.x as i32, from.y as i32),
+                (to.x as i32, to.y as i32),
+                color,
+                imageproc::pixelops::interpolate,
+            );
+        }
+
+        imgbuf.save("output/tectonics.png").unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+//! Temperature
+//! Estimates the annual mean, warmest month and coldest month temperature of every region in degrees Celsius. The
+//! sea level temperature falls from the equator towards the poles, and the seasonal range grows with latitude and
+//! with the distance from the ocean. The wind then carries warm or cold air downwind before the temperature is
+//! lowered by the lapse rate with height above sea level. The annual mean is written to the region.
+//!
+use crate::{
+    currents::Currents,
+    distance::{self, DistanceField},
+    dual_graph::{RegionEdge, RegionNode, RegionNodeIdx},
+    latitude::Latitude,
+    simple_wind,
+    water::{self, Water},
+    HasElevation, HasTemperature, HasWind,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+#[derive(Clone, Debug)]
+pub struct Temperatures<T: RealField> {
+    means: Vec<T>,
+    warmest: Vec<T>,
+    coldest: Vec<T>,
+}
+impl<T: RealField> Temperatures<T> {
+    pub fn mean(&self, region: RegionNodeIdx) -> T {
+        self.means[region.index()]
+    }
+
+    /// Mean temperature of the warmest month.
+    pub fn warmest(&self, region: RegionNodeIdx) -> T {
+        self.warmest[region.index()]
+    }
+
+    /// Mean temperature of the coldest month.
+    pub fn coldest(&self, region: RegionNodeIdx) -> T {
+        self.coldest[region.index()]
+    }
+
+    /// Difference between the warmest and coldest month.
+    pub fn range(&self, region: RegionNodeIdx) -> T {
+        self.warmest(region) - self.coldest(region)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    latitude: Latitude<T>,
+    sea_level: T,
+    equator: T,
+    pole: T,
+    lapse_rate: T,
+    height_scale: T,
+    maritime_range: T,
+    continental_range: T,
+    continental_distance: T,
+    advection: T,
+    advection_steps: usize,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_latitude(mut self, latitude: Latitude<T>) -> Self {
+        self.latitude = latitude;
+        self
+    }
+
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Annual mean sea level temperature at the equator and at the poles.
+    pub fn with_sea_level_temperatures(mut self, equator: T, pole: T) -> Self {
+        self.equator = equator;
+        self.pole = pole;
+        self
+    }
+
+    /// Cooling in degrees per kilometer of height.
+    pub fn with_lapse_rate(mut self, lapse_rate: T) -> Self {
+        self.lapse_rate = lapse_rate;
+        self
+    }
+
+    /// Meters of height per unit of elevation above sea level.
+    pub fn with_height_scale(mut self, height_scale: T) -> Self {
+        self.height_scale = height_scale;
+        self
+    }
+
+    /// Seasonal range at the poles over the ocean, and the additional range far inland.
+    pub fn with_ranges(mut self, maritime_range: T, continental_range: T) -> Self {
+        self.maritime_range = maritime_range;
+        self.continental_range = continental_range;
+        self
+    }
+
+    /// Distance from the coast in graph units over which the climate becomes continental.
+    pub fn with_continental_distance(mut self, continental_distance: T) -> Self {
+        self.continental_distance = continental_distance;
+        self
+    }
+
+    /// Fraction of the temperature taken from upwind regions every step, within `[0, 1]`, and the number of steps.
+    pub fn with_advection(mut self, advection: T, advection_steps: usize) -> Self {
+        self.advection = advection;
+        self.advection_steps = advection_steps;
+        self
+    }
+
+    pub fn latitude(&self) -> &Latitude<T> {
+        &self.latitude
+    }
+
+    pub fn sea_level(&self) -> T {
+        self.sea_level
+    }
+
+    pub fn default() -> Self {
+        Self {
+            latitude: Latitude::default(),
+            sea_level: 0.3.into(),
+            equator: 27.0.into(),
+            pole: (-20.0).into(),
+            lapse_rate: 6.5.into(),
+            height_scale: 8000.0.into(),
+            maritime_range: 10.0.into(),
+            continental_range: 40.0.into(),
+            continental_distance: 200.0.into(),
+            advection: 0.3.into(),
+            advection_steps: 10,
+        }
+    }
+}
+
+/// Mixes `values` with the values of the neighbors upwind of each region, weighted by how directly the wind of the
+/// neighbor blows towards the region.
+fn advect<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, local: &[(T, T, T)], settings: &Settings<T>) -> Vec<(T, T, T)>
+where
+    T: RealField + From<f32>,
+    V: HasWind<T>,
+    E: EdgeType,
+{
+    let upwind = simple_wind::upwind_of_regions(region_graph);
+    let mut values = local.to_vec();
+    let mut next = values.clone();
+
+    for _ in 0..settings.advection_steps {
+        for region in region_graph.node_indices() {
+            let (sum, total) =
+                upwind[region.index()]
+                    .iter()
+                    .fold(((T::zero(), T::zero(), T::zero()), T::zero()), |(sum, total), (neighbor, weight)| {
+                        let (mean, warmest, coldest) = values[neighbor.index()];
+                        (
+                            (sum.0 + mean * *weight, sum.1 + warmest * *weight, sum.2 + coldest * *weight),
+                            total + *weight,
+                        )
+                    });
+
+            let (mean, warmest, coldest) = local[region.index()];
+            next[region.index()] = if total > T::zero() {
+                let keep = T::one() - settings.advection;
+                (
+                    mean * keep + sum.0 / total * settings.advection,
+                    warmest * keep + sum.1 / total * settings.advection,
+                    coldest * keep + sum.2 / total * settings.advection,
+                )
+            } else {
+                (mean, warmest, coldest)
+            };
+        }
+        std::mem::swap(&mut values, &mut next);
+    }
+
+    values
+}
+
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Temperatures<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T>,
+    E: EdgeType,
+{
+    let (water, coast) = coast(region_graph, settings)?;
+    visit_with_water(region_graph, settings, &water, &coast, None)
+}
+
+/// Like `visit`, with the sea surface anomaly of the ocean currents added to the ocean before the wind carries it
+/// onshore.
+pub fn visit_with_currents<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    currents: &Currents<T>,
+) -> Result<Temperatures<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T>,
+    E: EdgeType,
+{
+    let (water, coast) = coast(region_graph, settings)?;
+    visit_with_water(region_graph, settings, &water, &coast, Some(currents))
+}
+
+/// Like `visit_with_currents` when given currents and `visit` otherwise, with the water at the settings sea level and
+/// its `distance::from_coast` field already computed, for callers that run the stage repeatedly over the same terrain.
+pub fn visit_with_water<T, V, E>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    water: &Water,
+    coast: &DistanceField<T>,
+    currents: Option<&Currents<T>>,
+) -> Result<Temperatures<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T>,
+    E: EdgeType,
+{
+    visit_with_anomaly(region_graph, settings, water, coast, |region| {
+        currents.map_or(T::zero(), |currents| currents.anomaly(region))
+    })
+}
+
+fn coast<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<(Water, DistanceField<T>), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    let water = water::visit(region_graph, &water::Settings::default().with_sea_level(settings.sea_level))?;
+    let coast = distance::from_coast(region_graph, &water, &distance::Settings::default())?;
+    Ok((water, coast))
+}
+
+fn visit_with_anomaly<T, V, E, F>(
+    region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>,
+    settings: &Settings<T>,
+    water: &Water,
+    coast: &DistanceField<T>,
+    anomaly: F,
+) -> Result<Temperatures<T>, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T> + HasWind<T> + HasTemperature<T>,
+    E: EdgeType,
+    F: Fn(RegionNodeIdx) -> T,
+{
+    if settings.advection < T::zero() || settings.advection > T::one() {
+        return Err(failure::format_err!("Temperature advection must be within [0, 1]"));
+    }
+    if settings.continental_distance <= T::zero() {
+        return Err(failure::format_err!("Continental distance must be positive"));
+    }
+
+    let latitudes = settings.latitude.visit(region_graph);
+    let half = T::from(0.5);
+
+    // Sea level temperatures before the wind mixes them
+    let local = region_graph
+        .node_indices()
+        .map(|region| {
+            let latitude = (latitudes.latitude(region) * T::pi() / T::from(180.0)).sin().abs();
+            let mean = settings.equator + (settings.pole - settings.equator) * latitude * latitude + anomaly(region);
+
+            // Without any coast the whole map is inland
+            let continentality = if water.is_ocean(region) {
+                T::zero()
+            } else {
+                coast
+                    .distance(region)
+                    .map_or(T::one(), |distance: T| T::one() - (-distance / settings.continental_distance).exp())
+            };
+            let range = (settings.maritime_range + settings.continental_range * continentality) * latitude;
+
+            (mean, mean + range * half, mean - range * half)
+        })
+        .collect::<Vec<_>>();
+    let advected = advect(region_graph, &local, settings);
+
+    let mut temperatures = Temperatures {
+        means: Vec::with_capacity(advected.len()),
+        warmest: Vec::with_capacity(advected.len()),
+        coldest: Vec::with_capacity(advected.len()),
+    };
+    for (region, (mean, warmest, coldest)) in region_graph.node_weights_mut().zip(advected) {
+        let height = (region.value.elevation() - settings.sea_level).max(T::zero()) * settings.height_scale;
+        let cooling = settings.lapse_rate * height / T::from(1000.0);
+
+        temperatures.means.push(mean - cooling);
+        temperatures.warmest.push(warmest - cooling);
+        temperatures.coldest.push(coldest - cooling);
+        region.value.set_temperature(mean - cooling);
+    }
+
+    Ok(temperatures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata::{self, node_for_coordinate, tests::ClimateInner};
+    use crate::simple_wind::{self, Circulation};
+    use nalgebra::Point2;
+
+    type TestInner = ClimateInner;
+
+    #[test]
+    pub fn temperature_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, mut rng) = peak_automata::tests::test_graph::<TestInner>();
+
+        // Flat land without any sea only varies with latitude
+        for region in region_graph.node_weights_mut() {
+            region.value.elevation = 0.3;
+        }
+        let flat = visit(&mut region_graph, &Settings::<f32>::default()).unwrap();
+        let at = |x: f32, y: f32| node_for_coordinate(&region_graph, Point2::new(x, y)).unwrap();
+        assert!(flat.mean(at(512.0, 512.0)) > flat.mean(at(512.0, 256.0)));
+        assert!(flat.mean(at(512.0, 256.0)) > flat.mean(at(512.0, 50.0)));
+        assert!(flat.range(at(512.0, 512.0)) < 1.0);
+        for region in region_graph.node_indices() {
+            assert!(flat.warmest(region) >= flat.mean(region) && flat.mean(region) >= flat.coldest(region));
+        }
+        let inland = [200.0, 350.0, 600.0, 900.0].iter().map(|x| at(*x, 250.0)).collect::<Vec<_>>();
+
+        // Ocean on the left, the seasonal range grows away from the coast
+        for region in region_graph.node_weights_mut() {
+            region.value.elevation = if region.pos.x < 300.0 { 0.0 } else { 0.3 };
+        }
+        let coastal = visit(&mut region_graph, &Settings::<f32>::default()).unwrap();
+        let ranges = inland.iter().map(|region| coastal.range(*region)).collect::<Vec<_>>();
+        assert!(ranges.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for region in region_graph.node_weights_mut() {
+            region.value.elevation = 0.0;
+        }
+        peak_automata::tests::raise_island(&mut region_graph, &mut rng);
+
+        let wind = simple_wind::Settings::<f32>::default().with_circulation(Circulation::default());
+        simple_wind::visit(&mut region_graph, &wind, &mut rng).unwrap();
+        let settings = Settings::<f32>::default();
+        let temperatures = visit(&mut region_graph, &settings).unwrap();
+
+        // Mountains are colder than the lowlands around them
+        for region in region_graph.node_indices() {
+            let temperature = temperatures.mean(region);
+            assert!((temperature - region_graph[region].value.temperature()).abs() < std::f32::EPSILON);
+            if region_graph[region].value.elevation() > 0.6 {
+                let lowland = flat.mean(region) - 0.3 * 8.0 * 6.5;
+                assert!(temperature < lowland + 5.0);
+            }
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let t = ((region.value.temperature() + 30.0) / 60.0).min(1.0).max(0.0);
+            if region.value.elevation() < 0.3 {
+                image::Rgb([0, 0, (96.0 + 96.0 * t) as u8])
+            } else {
+                image::Rgb([(255.0 * t) as u8, 64, (255.0 * (1.0 - t)) as u8])
+            }
+        });
+
+        imgbuf.save("output/temperature.png").unwrap();
+    }
+}
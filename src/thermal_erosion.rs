@@ -0,0 +1,186 @@
+//! Thermal Erosion
+//! Visits the graph, moving material from every region to its lower neighbors wherever the slope between them is
+//! steeper than the talus angle. Slopes use the real distance between region positions, so a spike on a single region
+//! is flattened while gentle slopes are left alone. Runs until no region moves more than a tolerance, or until the
+//! iteration limit is reached.
+//!
+use crate::{
+    dual_graph::{RegionEdge, RegionNode},
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{EdgeType, Graph};
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    talus_angle: T,
+    vertical_scale: T,
+    rate: T,
+    iterations: usize,
+    tolerance: T,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    /// Steepest stable slope in radians.
+    pub fn with_talus_angle(mut self, talus_angle: T) -> Self {
+        self.talus_angle = talus_angle;
+        self
+    }
+
+    /// Graph units per unit of elevation, used to turn elevation differences into slope angles.
+    pub fn with_vertical_scale(mut self, vertical_scale: T) -> Self {
+        self.vertical_scale = vertical_scale;
+        self
+    }
+
+    /// Fraction of the excess material moved per iteration, within `(0, 1]`.
+    pub fn with_rate(mut self, rate: T) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Upper bound on the number of iterations.
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// The pass is considered converged once no region changes by more than this amount in an iteration.
+    pub fn with_tolerance(mut self, tolerance: T) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            talus_angle: 0.6.into(),
+            vertical_scale: 100.0.into(),
+            rate: 0.5.into(),
+            iterations: 100,
+            tolerance: 0.0001.into(),
+        }
+    }
+}
+
+/// Runs the pass and returns the number of iterations it took, which equals the iteration limit if it did not converge.
+pub fn visit<T, V, E>(region_graph: &mut Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<usize, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: Default + HasElevation<T>,
+    E: EdgeType,
+{
+    if settings.rate <= T::zero() || settings.rate > T::one() {
+        return Err(failure::format_err!("Thermal erosion rate must be within (0, 1]"));
+    }
+
+    let talus = settings.talus_angle.tan() / settings.vertical_scale;
+    let half: T = 0.5.into();
+
+    let mut elevations = region_graph.node_weights_mut().map(|region| region.value.elevation()).collect::<Vec<_>>();
+    let mut changes = vec![T::zero(); elevations.len()];
+    let mut excess = Vec::new();
+
+    let mut iteration = 0;
+    while iteration < settings.iterations {
+        iteration += 1;
+
+        for region in region_graph.node_indices() {
+            let i = region.index();
+            let position = region_graph[region].pos;
+
+            // Height above the talus slope towards every lower neighbor
+            excess.clear();
+            excess.extend(region_graph.neighbors(region).filter_map(|neighbor| {
+                let distance = T::from(nalgebra::distance(&position, &region_graph[neighbor].pos));
+                let difference = elevations[i] - elevations[neighbor.index()] - talus * distance;
+                if difference > T::zero() {
+                    Some((neighbor.index(), difference))
+                } else {
+                    None
+                }
+            }));
+            if excess.is_empty() {
+                continue;
+            }
+
+            let total = excess.iter().fold(T::zero(), |acc, (_, difference)| acc + *difference);
+            let steepest = excess.iter().fold(T::zero(), |acc, (_, difference)| acc.max(*difference));
+            // Moving half the steepest excess levels the pair, the rest is spread by how far each neighbor is below
+            let moved = settings.rate * steepest * half;
+
+            changes[i] -= moved;
+            for (neighbor, difference) in &excess {
+                changes[*neighbor] += moved * *difference / total;
+            }
+        }
+
+        let mut largest = T::zero();
+        for (elevation, change) in elevations.iter_mut().zip(changes.iter_mut()) {
+            *elevation += *change;
+            largest = largest.max(change.abs());
+            *change = T::zero();
+        }
+
+        if largest <= settings.tolerance {
+            break;
+        }
+    }
+
+    for (region, elevation) in region_graph.node_weights_mut().zip(elevations) {
+        region.value.set_elevation(elevation);
+    }
+
+    Ok(iteration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+    use petgraph::visit::EdgeRef;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    fn steepest<E: EdgeType>(region_graph: &Graph<RegionNode<TestInner>, RegionEdge, E>) -> f32 {
+        region_graph.edge_references().fold(0.0, |acc, edge| {
+            let (a, b) = (&region_graph[edge.source()], &region_graph[edge.target()]);
+            let slope = (a.value.elevation() - b.value.elevation()).abs() / nalgebra::distance(&a.pos, &b.pos);
+            acc.max(slope)
+        })
+    }
+
+    #[test]
+    pub fn thermal_erosion_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        let mass_before = region_graph.node_indices().map(|idx| region_graph[idx].value.elevation()).sum::<f32>();
+        let steepest_before = steepest(&region_graph);
+
+        let settings = Settings::<f32>::default().with_iterations(500);
+        let iterations = visit(&mut region_graph, &settings).unwrap();
+
+        let mass_after = region_graph.node_indices().map(|idx| region_graph[idx].value.elevation()).sum::<f32>();
+        assert!(iterations > 0 && iterations <= 500);
+        assert!((mass_before - mass_after).abs() < 1.0e-2);
+        assert!(steepest(&region_graph) < steepest_before);
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| {
+            let color = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+
+            image::Rgb([color, color, color])
+        });
+
+        imgbuf.save("output/thermal_erosion.png").unwrap();
+    }
+}
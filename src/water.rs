@@ -0,0 +1,265 @@
+//! Water
+//! Classifies the regions of the graph into ocean, lake and land using a sea level. Ocean is flood filled from the
+//! regions on the edge of the map, so regions below sea level that the sea cannot reach become lakes instead. Edges
+//! between ocean and the rest are marked as coast, and connected land is labelled as landmasses, which can be used
+//! to drop small islands or to require a minimum continent size.
+//!
+use crate::{
+    dual_graph::{is_boundary_region, RegionEdge, RegionEdgeIdx, RegionNode, RegionNodeIdx},
+    HasElevation,
+};
+use nalgebra::RealField;
+use petgraph::{visit::EdgeRef, EdgeType, Graph};
+use std::collections::VecDeque;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Surface {
+    Ocean,
+    /// Below sea level but cut off from the ocean.
+    Lake,
+    Land,
+}
+
+#[derive(Clone, Debug)]
+pub struct Landmass {
+    pub id: usize,
+    /// Land and lake regions of the landmass.
+    pub regions: Vec<RegionNodeIdx>,
+}
+impl Landmass {
+    pub fn size(&self) -> usize {
+        self.regions.len()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Water {
+    surfaces: Vec<Surface>,
+    coast: Vec<bool>,
+    landmass_of: Vec<Option<usize>>,
+    /// Landmasses ordered from largest to smallest, so the ID of the largest one is zero.
+    pub landmasses: Vec<Landmass>,
+}
+impl Water {
+    pub fn surface(&self, region: RegionNodeIdx) -> Surface {
+        self.surfaces[region.index()]
+    }
+
+    pub fn is_ocean(&self, region: RegionNodeIdx) -> bool {
+        self.surface(region) == Surface::Ocean
+    }
+
+    /// Returns true if the edge separates ocean from land or lake.
+    pub fn is_coast(&self, edge: RegionEdgeIdx) -> bool {
+        self.coast[edge.index()]
+    }
+
+    pub fn coast_edges(&self) -> impl Iterator<Item = RegionEdgeIdx> + '_ {
+        self.coast
+            .iter()
+            .enumerate()
+            .filter(|(_, coast)| **coast)
+            .map(|(idx, _)| RegionEdgeIdx::new(idx))
+    }
+
+    /// ID of the landmass a region belongs to, an index into `landmasses`, `None` for ocean.
+    pub fn landmass(&self, region: RegionNodeIdx) -> Option<usize> {
+        self.landmass_of[region.index()]
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    sea_level: T,
+    min_landmass: usize,
+    min_continent: usize,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_sea_level(mut self, sea_level: T) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    /// Landmasses with fewer regions than this are turned into ocean.
+    pub fn with_min_landmass(mut self, min_landmass: usize) -> Self {
+        self.min_landmass = min_landmass;
+        self
+    }
+
+    /// Fail unless the largest landmass has at least this many regions.
+    pub fn with_min_continent(mut self, min_continent: usize) -> Self {
+        self.min_continent = min_continent;
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            sea_level: 0.3.into(),
+            min_landmass: 0,
+            min_continent: 0,
+        }
+    }
+}
+
+pub fn visit<T, V, E>(region_graph: &Graph<RegionNode<V>, RegionEdge, E>, settings: &Settings<T>) -> Result<Water, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasElevation<T>,
+    E: EdgeType,
+{
+    let count = region_graph.node_count();
+    let mut surfaces = region_graph
+        .node_indices()
+        .map(|region| {
+            if region_graph[region].value.elevation() < settings.sea_level {
+                Surface::Lake
+            } else {
+                Surface::Land
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Flood the ocean in from the edge of the map, anything below sea level it cannot reach stays a lake
+    let mut queue = region_graph
+        .node_indices()
+        .filter(|region| surfaces[region.index()] == Surface::Lake && is_boundary_region(region_graph, *region))
+        .collect::<VecDeque<_>>();
+    for region in &queue {
+        surfaces[region.index()] = Surface::Ocean;
+    }
+    while let Some(region) = queue.pop_front() {
+        for neighbor in region_graph.neighbors(region) {
+            if surfaces[neighbor.index()] == Surface::Lake {
+                surfaces[neighbor.index()] = Surface::Ocean;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    let mut landmasses = Vec::new();
+    let mut visited = vec![false; count];
+    for start in region_graph.node_indices() {
+        if visited[start.index()] || surfaces[start.index()] == Surface::Ocean {
+            continue;
+        }
+
+        let mut regions = Vec::new();
+        visited[start.index()] = true;
+        queue.push_back(start);
+        while let Some(region) = queue.pop_front() {
+            regions.push(region);
+            for neighbor in region_graph.neighbors(region) {
+                if !visited[neighbor.index()] && surfaces[neighbor.index()] != Surface::Ocean {
+                    visited[neighbor.index()] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        if regions.len() < settings.min_landmass {
+            for region in regions {
+                surfaces[region.index()] = Surface::Ocean;
+            }
+        } else {
+            landmasses.push(Landmass { id: 0, regions });
+        }
+    }
+
+    landmasses.sort_by_key(|landmass| std::cmp::Reverse(landmass.size()));
+    let mut landmass_of = vec![None; count];
+    for (id, landmass) in landmasses.iter_mut().enumerate() {
+        landmass.id = id;
+        for region in &landmass.regions {
+            landmass_of[region.index()] = Some(id);
+        }
+    }
+
+    let largest = landmasses.first().map_or(0, Landmass::size);
+    if largest < settings.min_continent {
+        return Err(failure::format_err!(
+            "Largest landmass has {} regions, at least {} are required",
+            largest,
+            settings.min_continent
+        ));
+    }
+
+    let coast = region_graph
+        .edge_references()
+        .map(|edge| {
+            let (source, target) = (surfaces[edge.source().index()], surfaces[edge.target().index()]);
+            (source == Surface::Ocean) != (target == Surface::Ocean)
+        })
+        .collect();
+
+    Ok(Water {
+        surfaces,
+        coast,
+        landmass_of,
+        landmasses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    #[derive(Default)]
+    struct TestInner {
+        elevation: f32,
+        surface: Option<Surface>,
+    }
+    impl HasElevation<f32> for TestInner {
+        fn elevation(&self) -> f32 {
+            self.elevation
+        }
+        fn set_elevation(&mut self, height: f32) {
+            self.elevation = height;
+        }
+    }
+
+    #[test]
+    pub fn water_visitor() {
+        let mut imgbuf = peak_automata::tests::canvas();
+        let (mut region_graph, border_graph, _) = peak_automata::tests::island::<TestInner>();
+
+        let water = visit(&region_graph, &Settings::<f32>::default()).unwrap();
+
+        for region in region_graph.node_indices() {
+            let elevation = region_graph[region].value.elevation();
+            match water.surface(region) {
+                Surface::Land => assert!(elevation >= 0.3),
+                Surface::Lake => assert!(elevation < 0.3 && region_graph.neighbors(region).all(|neighbor| !water.is_ocean(neighbor))),
+                Surface::Ocean => assert!(elevation < 0.3 && water.landmass(region).is_none()),
+            }
+        }
+        let land = region_graph.node_indices().filter(|region| !water.is_ocean(*region)).count();
+        assert_eq!(water.landmasses.iter().map(Landmass::size).sum::<usize>(), land);
+        assert!(water.landmasses.windows(2).all(|pair| pair[0].size() >= pair[1].size()));
+        for edge in water.coast_edges() {
+            let (a, b) = region_graph.edge_endpoints(edge).unwrap();
+            assert_ne!(water.is_ocean(a), water.is_ocean(b));
+        }
+
+        // Dropping small islands leaves only large landmasses
+        let largest = water.landmasses[0].size();
+        let continents = visit(&region_graph, &Settings::<f32>::default().with_min_landmass(largest)).unwrap();
+        assert!(continents.landmasses.iter().all(|landmass| landmass.size() >= largest));
+        assert!(visit(&region_graph, &Settings::<f32>::default().with_min_continent(largest + 1)).is_err());
+
+        for region in region_graph.node_indices() {
+            region_graph[region].value.surface = Some(water.surface(region));
+        }
+
+        peak_automata::tests::draw_regions(&mut imgbuf, &region_graph, &border_graph, |region| match region.value.surface {
+            Some(Surface::Ocean) => image::Rgb([0, 0, 120]),
+            Some(Surface::Lake) => image::Rgb([60, 120, 255]),
+            _ => {
+                let shade = (255.0 * region.value.elevation().min(1.0).max(0.0)) as u8;
+                image::Rgb([shade, shade / 2 + 100, shade / 2])
+            }
+        });
+
+        imgbuf.save("output/water.png").unwrap();
+    }
+}
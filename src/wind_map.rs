@@ -0,0 +1,460 @@
+//! Wind map
+//! Renders the wind of a region graph into an RGB image, either as evenly spaced streamlines or as a grid of arrows,
+//! colored from slow to fast. The wind between regions is interpolated from the closest region and its neighbors by
+//! inverse distance. Streamlines are traced both ways from seed points and stop when they come too close to another
+//! line, leave the image or reach calm air, so regions without wind are left blank instead of drawn.
+//!
+use crate::{
+    dual_graph::{bounds, BorderEdge, BorderNode, RegionEdge, RegionNode},
+    spatial::SpatialIndex,
+    HasWind,
+};
+use image::{Rgb, RgbImage};
+use nalgebra::{Point2, RealField, Vector2};
+use petgraph::{EdgeType, Graph};
+
+/// Wind speeds below this are treated as calm and not drawn.
+const CALM: f32 = 1.0e-6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Style {
+    /// Lines following the wind, spaced roughly evenly over the image.
+    Streamlines,
+    /// One arrow pointing downwind in every cell of a grid.
+    Arrows,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Settings<T: RealField> {
+    width: u32,
+    height: u32,
+    style: Style,
+    spacing: f32,
+    max_length: f32,
+    max_speed: Option<T>,
+}
+impl<T: RealField + From<f32>> Settings<T> {
+    pub fn with_resolution(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Distance in pixels between streamlines, or between arrows of the grid.
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Longest streamline in pixels traced from a seed point in each direction.
+    pub fn with_max_length(mut self, max_length: f32) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Speed drawn in the fastest color, otherwise the fastest wind of the graph.
+    pub fn with_max_speed(mut self, max_speed: T) -> Self {
+        self.max_speed = Some(max_speed);
+        self
+    }
+
+    pub fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 1024,
+            style: Style::Streamlines,
+            spacing: 16.0,
+            max_length: 400.0,
+            max_speed: None,
+        }
+    }
+}
+
+/// Color ramp from slow dark blue through cyan and yellow to fast red.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn speed_color(fraction: f32) -> Rgb<u8> {
+    const STOPS: [(f32, [f32; 3]); 4] = [
+        (0.0, [40.0, 60.0, 170.0]),
+        (0.35, [0.0, 200.0, 220.0]),
+        (0.7, [240.0, 220.0, 40.0]),
+        (1.0, [230.0, 40.0, 30.0]),
+    ];
+    let fraction = if fraction.is_nan() { 0.0 } else { fraction.max(0.0).min(1.0) };
+
+    let upper = STOPS.iter().position(|(stop, _)| fraction <= *stop).unwrap_or(STOPS.len() - 1).max(1);
+    let ((low, from), (high, to)) = (STOPS[upper - 1], STOPS[upper]);
+    let t = (fraction - low) / (high - low);
+    let mut color = [0; 3];
+    for (channel, (from, to)) in color.iter_mut().zip(from.iter().zip(&to)) {
+        *channel = (from + (to - from) * t).round() as u8;
+    }
+    Rgb(color)
+}
+
+/// Wind sampled in pixel coordinates of the image.
+struct Field<'a, V, E: EdgeType> {
+    region_graph: &'a Graph<RegionNode<V>, RegionEdge, E>,
+    index: SpatialIndex,
+    winds: Vec<Vector2<f32>>,
+    low: Point2<f32>,
+    scale: Vector2<f32>,
+    width: f32,
+    height: f32,
+}
+impl<V, E: EdgeType> Field<'_, V, E> {
+    fn contains(&self, pixel: Point2<f32>) -> bool {
+        pixel.x >= 0.0 && pixel.y >= 0.0 && pixel.x < self.width && pixel.y < self.height
+    }
+
+    /// Interpolated wind at the pixel, `None` outside the image or for an empty graph.
+    fn wind(&self, pixel: Point2<f32>) -> Option<Vector2<f32>> {
+        if !self.contains(pixel) {
+            return None;
+        }
+        let point = self.low + pixel.coords.component_mul(&self.scale);
+        let nearest = self.index.nearest(point)?;
+
+        let mut sum = Vector2::zeros();
+        let mut total = 0.0;
+        for region in std::iter::once(nearest).chain(self.region_graph.neighbors(nearest)) {
+            let distance = nalgebra::distance_squared(&self.region_graph[region].pos, &point);
+            if distance <= std::f32::EPSILON {
+                return Some(self.winds[region.index()]);
+            }
+            sum += self.winds[region.index()] / distance;
+            total += 1.0 / distance;
+        }
+        Some(sum / total)
+    }
+
+    /// Unit direction of the wind in pixels together with its speed, `None` in calm air.
+    fn direction(&self, pixel: Point2<f32>) -> Option<(Vector2<f32>, f32)> {
+        let wind = self.wind(pixel)?;
+        let speed = wind.norm();
+        if speed <= CALM {
+            return None;
+        }
+        wind.component_div(&self.scale).try_normalize(CALM).map(|direction| (direction, speed))
+    }
+}
+
+/// Points of all streamlines drawn so far, bucketed into cells as wide as the line spacing.
+struct Occupancy {
+    cell: f32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<Point2<f32>>>,
+}
+impl Occupancy {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn new(width: f32, height: f32, cell: f32) -> Self {
+        let columns = (width / cell) as usize + 1;
+        let rows = (height / cell) as usize + 1;
+        Self {
+            cell,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn cell_of(&self, point: Point2<f32>) -> (usize, usize) {
+        let column = (point.x / self.cell).max(0.0) as usize;
+        let row = (point.y / self.cell).max(0.0) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    /// Whether no point lies within `distance`, which must not exceed the cell size.
+    fn is_free(&self, point: Point2<f32>, distance: f32) -> bool {
+        let (column, row) = self.cell_of(point);
+        let squared = distance * distance;
+        for y in row.saturating_sub(1)..=(row + 1).min(self.rows - 1) {
+            for x in column.saturating_sub(1)..=(column + 1).min(self.columns - 1) {
+                if self.cells[y * self.columns + x]
+                    .iter()
+                    .any(|other| nalgebra::distance_squared(other, &point) < squared)
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn insert(&mut self, point: Point2<f32>) {
+        let (column, row) = self.cell_of(point);
+        self.cells[row * self.columns + column].push(point);
+    }
+}
+
+/// Follows the wind from the start, downwind for a positive sign and upwind for a negative one, with midpoint steps of
+/// one pixel.
+fn trace<V, E: EdgeType>(
+    field: &Field<'_, V, E>,
+    occupancy: &Occupancy,
+    start: Point2<f32>,
+    sign: f32,
+    steps: usize,
+    separation: f32,
+) -> Vec<(Point2<f32>, f32)> {
+    let mut points = Vec::new();
+    let mut pixel = start;
+    for _ in 0..steps {
+        let (direction, _) = match field.direction(pixel) {
+            Some(direction) => direction,
+            None => break,
+        };
+        let (direction, speed) = match field.direction(pixel + direction * sign * 0.5) {
+            Some(direction) => direction,
+            None => break,
+        };
+        let next = pixel + direction * sign;
+        if !field.contains(next) || !occupancy.is_free(next, separation) {
+            break;
+        }
+        points.push((next, speed));
+        pixel = next;
+    }
+    points
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn draw_streamlines<V, E: EdgeType>(field: &Field<'_, V, E>, image: &mut RgbImage, spacing: f32, max_length: f32, max_speed: f32) {
+    // Lines are seeded a full spacing away from the others, and stop when they get closer than half of it
+    let separation = spacing * 0.5;
+    let mut occupancy = Occupancy::new(field.width, field.height, spacing);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let steps = max_length.max(0.0) as usize;
+
+    let mut y = separation * 0.5;
+    while y < field.height {
+        let mut x = separation * 0.5;
+        while x < field.width {
+            let start = Point2::new(x, y);
+            x += separation;
+            let start_speed = match field.direction(start) {
+                Some((_, speed)) if occupancy.is_free(start, spacing) => speed,
+                _ => continue,
+            };
+
+            let mut line = trace(field, &occupancy, start, -1.0, steps, separation);
+            line.reverse();
+            line.push((start, start_speed));
+            line.extend(trace(field, &occupancy, start, 1.0, steps, separation));
+            if line.len() < 2 {
+                continue;
+            }
+
+            for ((from, speed), (to, _)) in line.iter().zip(line.iter().skip(1)) {
+                line_segment(image, *from, *to, speed_color(speed / max_speed));
+            }
+            for (point, _) in line {
+                occupancy.insert(point);
+            }
+        }
+        y += separation;
+    }
+}
+
+fn draw_arrows<V, E: EdgeType>(field: &Field<'_, V, E>, image: &mut RgbImage, spacing: f32, max_speed: f32) {
+    let length = spacing * 0.8;
+    let (cos, sin) = (std::f32::consts::FRAC_PI_6.cos(), std::f32::consts::FRAC_PI_6.sin());
+
+    let mut y = spacing * 0.5;
+    while y < field.height {
+        let mut x = spacing * 0.5;
+        while x < field.width {
+            let center = Point2::new(x, y);
+            x += spacing;
+            let (direction, speed) = match field.direction(center) {
+                Some(direction) => direction,
+                None => continue,
+            };
+
+            let color = speed_color(speed / max_speed);
+            let (tail, head) = (center - direction * length * 0.5, center + direction * length * 0.5);
+            line_segment(image, tail, head, color);
+            // Barbs are the reversed direction turned 30 degrees either way
+            for side in &[1.0, -1.0] {
+                let barb = Vector2::new(
+                    -direction.x * cos + direction.y * sin * side,
+                    -direction.y * cos - direction.x * sin * side,
+                );
+                line_segment(image, head, head + barb * length * 0.35, color);
+            }
+        }
+        y += spacing;
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn line_segment(image: &mut RgbImage, from: Point2<f32>, to: Point2<f32>, color: Rgb<u8>) {
+    imageproc::drawing::draw_antialiased_line_segment_mut(
+        image,
+        (from.x.round() as i32, from.y.round() as i32),
+        (to.x.round() as i32, to.y.round() as i32),
+        color,
+        imageproc::pixelops::interpolate,
+    );
+}
+
+/// Draws the wind over an existing image, such as a rendered heightmap, placing it over the bounds of the border graph.
+/// The resolution of the image is used instead of the one in the settings.
+pub fn draw<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    settings: &Settings<T>,
+    image: &mut RgbImage,
+) -> Result<(), failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasWind<T>,
+    E: EdgeType,
+{
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(failure::format_err!("Wind map resolution must not be zero"));
+    }
+    if settings.spacing.is_nan() || settings.spacing < 1.0 {
+        return Err(failure::format_err!("Wind map spacing must be at least one pixel"));
+    }
+    let (low, high) = bounds(border_graph).ok_or_else(|| failure::format_err!("Border graph has no area to draw over"))?;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let to_f32 = |value: T| nalgebra::try_convert::<T, f64>(value).unwrap_or(0.0) as f32;
+    let winds = region_graph
+        .node_indices()
+        .map(|region| {
+            let wind = region_graph[region].value.wind_vector();
+            let wind = Vector2::new(to_f32(wind.x), to_f32(wind.y));
+            // Broken values are drawn as calm rather than poisoning the interpolation
+            if wind.x.is_finite() && wind.y.is_finite() {
+                wind
+            } else {
+                Vector2::zeros()
+            }
+        })
+        .collect::<Vec<_>>();
+    let max_speed = settings
+        .max_speed
+        .map_or_else(|| winds.iter().fold(0.0, |max: f32, wind| max.max(wind.norm())), to_f32);
+    let max_speed = if max_speed > CALM { max_speed } else { 1.0 };
+
+    #[allow(clippy::cast_precision_loss)]
+    let (width, height) = (width as f32, height as f32);
+    let field = Field {
+        region_graph,
+        index: SpatialIndex::new(region_graph),
+        winds,
+        low,
+        scale: Vector2::new((high.x - low.x) / width, (high.y - low.y) / height),
+        width,
+        height,
+    };
+
+    match settings.style {
+        Style::Streamlines => draw_streamlines(&field, image, settings.spacing, settings.max_length, max_speed),
+        Style::Arrows => draw_arrows(&field, image, settings.spacing, max_speed),
+    }
+    Ok(())
+}
+
+/// Draws the wind on black at the resolution of the settings.
+pub fn to_image<T, V, B, E>(
+    region_graph: &Graph<RegionNode<V>, RegionEdge, E>,
+    border_graph: &Graph<BorderNode<B>, BorderEdge, E>,
+    settings: &Settings<T>,
+) -> Result<RgbImage, failure::Error>
+where
+    T: RealField + From<f32>,
+    V: HasWind<T>,
+    E: EdgeType,
+{
+    let mut image = RgbImage::new(settings.width, settings.height);
+    draw(region_graph, border_graph, settings, &mut image)?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peak_automata;
+
+    struct TestInner {
+        wind: Vector2<f32>,
+    }
+    impl Default for TestInner {
+        fn default() -> Self {
+            Self { wind: Vector2::zeros() }
+        }
+    }
+    impl HasWind<f32> for TestInner {
+        fn wind_vector(&self) -> Vector2<f32> {
+            self.wind
+        }
+        fn set_wind_vector(&mut self, wind: Vector2<f32>) {
+            self.wind = wind;
+        }
+    }
+
+    fn drawn(image: &RgbImage, columns: std::ops::Range<u32>) -> usize {
+        image
+            .enumerate_pixels()
+            .filter(|(x, _, pixel)| columns.contains(x) && pixel.0 != [0, 0, 0])
+            .count()
+    }
+
+    #[test]
+    pub fn wind_map_styles() {
+        let (mut region_graph, border_graph, _) = peak_automata::tests::test_graph::<TestInner>();
+
+        // A gyre over the left half and calm air over the right, where nothing may be drawn
+        for region in region_graph.node_weights_mut() {
+            let offset = region.pos - Point2::new(256.0, 512.0);
+            let wind = if region.pos.x < 512.0 {
+                Vector2::new(-offset.y, offset.x) / 256.0
+            } else {
+                Vector2::zeros()
+            };
+            region.value.set_wind_vector(wind);
+        }
+
+        for (style, name) in &[(Style::Streamlines, "streamlines"), (Style::Arrows, "arrows")] {
+            let settings = Settings::<f32>::default().with_resolution(512, 512).with_style(*style);
+            let image = to_image(&region_graph, &border_graph, &settings).unwrap();
+            assert_eq!(image.dimensions(), (512, 512));
+            assert!(drawn(&image, 0..256) > 512 * 8);
+            assert_eq!(drawn(&image, 280..512), 0);
+            image.save(format!("output/wind_map_{}.png", name)).unwrap();
+        }
+
+        // Evenly spaced streamlines cover the gyre without piling up in its eye
+        let settings = Settings::<f32>::default().with_resolution(512, 512).with_spacing(24.0);
+        let sparse = to_image(&region_graph, &border_graph, &settings).unwrap();
+        let dense = to_image(&region_graph, &border_graph, &settings.clone().with_spacing(8.0)).unwrap();
+        assert!(drawn(&sparse, 0..256) < drawn(&dense, 0..256));
+
+        // Drawing over an existing image keeps its resolution and background
+        let mut image = RgbImage::from_pixel(128, 64, Rgb([10, 10, 10]));
+        draw(&region_graph, &border_graph, &settings.clone().with_style(Style::Arrows), &mut image).unwrap();
+        assert_eq!(image.get_pixel(127, 32), &Rgb([10, 10, 10]));
+
+        assert!(to_image(&region_graph, &border_graph, &settings.clone().with_spacing(0.0)).is_err());
+        assert!(to_image(&region_graph, &border_graph, &settings.clone().with_resolution(0, 512)).is_err());
+    }
+
+    #[test]
+    pub fn wind_map_colors() {
+        assert_eq!(speed_color(0.0), Rgb([40, 60, 170]));
+        assert_eq!(speed_color(1.0), Rgb([230, 40, 30]));
+        assert_eq!(speed_color(2.0), speed_color(1.0));
+        assert_eq!(speed_color(std::f32::NAN), speed_color(0.0));
+        assert_eq!(speed_color(0.35), Rgb([0, 200, 220]));
+    }
+}